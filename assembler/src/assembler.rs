@@ -1,19 +1,33 @@
 use std::io::{self, BufRead, Write};
 use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
 use crate::parser::*;
 use crate::encoder::*;
+use crate::explain::*;
+use crate::lint::*;
+use crate::preprocessor::*;
+use crate::macros::*;
+use crate::object::Reloc;
 
-fn write_error(line: &str, line_num: u32, ins_ptr: u16, msg: &str){
-	println!("error: {}\n[ip:{},ln:{}] | {}\n", msg, ins_ptr, line_num, line);
+fn write_error(code: &str, line: &str, line_num: u32, ins_ptr: u16, msg: &str){
+	println!("error[{}]: {}\n[ip:{},ln:{}] | {}\n", code, msg, ins_ptr, line_num, line);
 }
 
-fn write_pos_error(found: char, pos: usize, line: &str, line_num: u32, ins_ptr: u16, msg: &str){
+/// Caret line pointing at byte `pos` of `line`; tabs are kept so the caret aligns however
+/// wide the terminal renders them.
+fn pos_pointer(prefix_len: usize, pos: usize, line: &str) -> String {
+	let lead: String = line[..pos].chars().map(|c| if c == '\t' { '\t' } else { '~' }).collect();
+	format!("{}{}^", " ".repeat(prefix_len), lead)
+}
+
+fn write_pos_error(code: &str, found: char, pos: usize, line: &str, line_num: u32, ins_ptr: u16, msg: &str){
 	let dat = format!("[ip:{},ln:{}] | ", ins_ptr, line_num);
-	let pnt = format!("{}{}^", " ".repeat(dat.len()), "~".repeat(pos - 1));
-	println!("Unexpected character '{}' at pos '{}'. {}\n{}{}\n{}", found, pos, msg, dat, line, pnt);
+	let pnt = pos_pointer(dat.len(), pos, line);
+	println!("error[{}]: Unexpected character '{}' at pos '{}'. {}\n{}{}\n{}", code, found, pos, msg, dat, line, pnt);
 }
 
 fn write_parse_error(e: &ParseError, line: &str, line_num: u32, ins_ptr: u16) {
+	let code = e.code();
 	match e {
 		ParseError::UnknownMne{mne_type, mne_buf} => {
 			let mne_type_str = match mne_type {
@@ -22,63 +36,312 @@ fn write_parse_error(e: &ParseError, line: &str, line_num: u32, ins_ptr: u16) {
 			};
 			let mne_str = std::str::from_utf8(mne_buf.as_ref()).unwrap().trim();
 			let msg = format!("Unknown {}mnemonic '{}'", mne_type_str, mne_str);
-			write_error(line, line_num, ins_ptr, &msg);
+			write_error(code, line, line_num, ins_ptr, &msg);
 		},
 		ParseError::ExpectedFirstSymChar{found, pos} => {
-			write_pos_error(*found, *pos, line, line_num, ins_ptr, "Expected valid first symbol character.");
+			write_pos_error(code, *found, *pos, line, line_num, ins_ptr, "Expected valid first symbol character.");
 		},
 		ParseError::ExpectedSymChar{found, pos} => {
-			write_pos_error(*found, *pos, line, line_num, ins_ptr, "Expected valid symbol character.");
+			write_pos_error(code, *found, *pos, line, line_num, ins_ptr, "Expected valid symbol character.");
 		},
 		ParseError::ExpectedDigit{found, pos} => {
-			write_pos_error(*found, *pos, line, line_num, ins_ptr, "Expected digit.");
+			write_pos_error(code, *found, *pos, line, line_num, ins_ptr, "Expected digit.");
 		},
 		ParseError::UnexpectedChar{found, pos} => {
-			write_pos_error(*found, *pos, line, line_num, ins_ptr, "");
+			write_pos_error(code, *found, *pos, line, line_num, ins_ptr, "");
 		},
 		ParseError::DuplicateLabel => {
-			write_error(line, line_num, ins_ptr, "Duplicate label definition!");
+			write_error(code, line, line_num, ins_ptr, "Duplicate label definition!");
 		},
 		ParseError::AInsMissingArg => {
-			write_error(line, line_num, ins_ptr, "Expected argument after opening '@' character for A-instruction.");
+			write_error(code, line, line_num, ins_ptr, "Expected argument after opening '@' character for A-instruction.");
 		},
 		ParseError::LInsMissingSym => {
-			write_error(line, line_num, ins_ptr, "Expected symbol after opening '(' character for L-instruction.");
+			write_error(code, line, line_num, ins_ptr, "Expected symbol after opening '(' character for L-instruction.");
 		},
 		ParseError::LInsMissingClose => {
-			write_error(line, line_num, ins_ptr, "Expected closing ')' character for label.");
+			write_error(code, line, line_num, ins_ptr, "Expected closing ')' character for label.");
 		},
 		ParseError::SymOverflow => {
 			let msg = format!("Symbol too large! Max symbol length is {} characters.", MAX_SYM_LEN);
-			write_error(line, line_num, ins_ptr, &msg);
+			write_error(code, line, line_num, ins_ptr, &msg);
 		},
 		ParseError::IntOverflow => {
-			write_error(line, line_num, ins_ptr, "Integer too large! Overflows u16 memory register.");
+			write_error(code, line, line_num, ins_ptr, "Integer too large! Overflows u16 memory register.");
 		},
 		ParseError::NotASCII => {
-			write_error(line, line_num, ins_ptr, "Found unicode character! Unicode not supported; ASCII only.");
+			write_error(code, line, line_num, ins_ptr, "Found unicode character! Unicode not supported; ASCII only.");
 		},
 		ParseError::CInsNop => {
-			write_error(line, line_num, ins_ptr, "Invalid c-instruction; has no effect! Requires a Dest or Jump term.");
+			write_error(code, line, line_num, ins_ptr, "Invalid c-instruction; has no effect! Requires a Dest or Jump term.");
+		},
+		ParseError::RelOutOfRange => {
+			write_error(code, line, line_num, ins_ptr, "Relative address out of range! Must be within ROM addresses 0 to 32767.");
+		},
+		ParseError::HexOverflow => {
+			write_error(code, line, line_num, ins_ptr, "Hex literal too large! Max value is 0x7FFF.");
+		},
+		ParseError::BinOverflow => {
+			write_error(code, line, line_num, ins_ptr, "Binary literal too large! Max value is 0b111111111111111.");
+		},
+		ParseError::CharMissingClose => {
+			write_error(code, line, line_num, ins_ptr, "Expected closing ' character for character literal.");
+		},
+	}
+}
+
+fn write_probable_typo_warning(severity: Severity, sym: &str, label: &str, sym_ref: &SymRef) {
+	println!("{}[{}]: Variable '{}' is only referenced once; did you mean label '{}'?\n[ip:{},ln:{}] | {}\n",
+		severity.as_str(), PROBABLE_TYPO_CODE, sym, label, sym_ref.ins_ptr, sym_ref.line_num, sym_ref.line);
+}
+
+fn write_directive_error(e: &DirectiveError, line: &str, line_num: u32, ins_ptr: u16) {
+	match e {
+		DirectiveError::Unterminated{line, line_num} => write_error(DIRECTIVE_CODE, line, *line_num, ins_ptr, e.as_str()),
+		_ => write_error(DIRECTIVE_CODE, line, line_num, ins_ptr, e.as_str()),
+	}
+}
+
+fn write_macro_error(e: &MacroError, line: &str, line_num: u32, ins_ptr: u16) {
+	match e {
+		MacroError::Unterminated{line, line_num} => write_error(MACRO_CODE, line, *line_num, ins_ptr, e.as_str()),
+		MacroError::WrongArgCount{expected, found} => {
+			let msg = format!("{} Expected {} but found {}.", e.as_str(), expected, found);
+			write_error(MACRO_CODE, line, line_num, ins_ptr, &msg);
 		},
+		_ => write_error(MACRO_CODE, line, line_num, ins_ptr, e.as_str()),
 	}
 }
 
+fn write_max_errors_error(max_errors: u32) {
+	println!("error: Aborting after {} errors! Assembly terminated!", max_errors);
+}
+
 fn write_ram_exhausted_error() {
-	println!("RAM exhausted! Assembly terminated!");
+	println!("error[{}]: RAM exhausted! Assembly terminated!", RAM_EXHAUSTED_CODE);
 }
 
 fn write_rom_exhausted_error(line: &str, line_num: u32, ins_ptr: u16) {
-	write_error(line, line_num, ins_ptr, "ROM exhausted! Assembly terminated!");
+	write_error(ROM_EXHAUSTED_CODE, line, line_num, ins_ptr, "ROM exhausted! Assembly terminated!");
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+	Warning,
+	Error,
+}
+
+impl Severity {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct AssembleOptions {
+	/// Stop assembling after this many errors; None to report every error.
+	pub max_errors: Option<u32>,
+	/// Report warnings as errors, failing the assembly.
+	pub warnings_as_errors: bool,
+	/// Symbols defined for conditional assembly; see [`Conditionals`].
+	pub defines: HashSet<String>,
+	/// Record each assembled line in [`Program::lines`]; for a listing.
+	pub listing: bool,
+	/// Assemble an object to link with others, allowing externs; see [`crate::object`].
+	pub object: bool,
+}
+
+impl Default for AssembleOptions {
+	fn default() -> Self {
+		AssembleOptions{max_errors: Some(10), warnings_as_errors: false, defines: HashSet::new(), listing: false, object: false}
+	}
+}
+
+/// A line of source which was assembled.
+#[derive(Debug, PartialEq)]
+pub struct SourceLine {
+	pub line_num: u32,
+	pub text: String,
+	/// ROM address of the first instruction on the line.
+	pub rom_address: u16,
+	/// Number of instructions on the line, excluding labels; their words are
+	/// `words[rom_address..][..ins_count]`.
+	pub ins_count: u16,
+	/// Symbols the line references or declares, by id.
+	pub sym_ids: Vec<usize>,
+}
+
+/// A symbol and the address it resolved to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+	pub name: String,
+	pub address: u16,
+	pub usage: SymUse,
+}
+
+/// The result of assembling a program.
+#[derive(Debug, Default)]
+pub struct Program {
+	pub line_count: u32,
+	pub ins_count: u16,
+	pub error_count: u32,
+	/// Encoded instructions; empty if there were any errors.
+	pub words: Vec<u16>,
+	/// Every line assembled when [`AssembleOptions::listing`] is set, otherwise empty. Lines
+	/// excluded by conditional assembly, and directives, are omitted.
+	pub lines: Vec<SourceLine>,
+	/// Every symbol indexed by id, including the predefined symbols; empty if there were
+	/// any errors.
+	pub symbols: Vec<Symbol>,
+	/// The relocation of each word when [`AssembleOptions::object`] is set, otherwise empty.
+	pub relocs: Vec<Reloc>,
+	/// Symbols declared `.global`, in order of declaration.
+	pub globals: Vec<Symbol>,
 }
 
+/// Lines of a reader ending in any of "\n", "\r\n" or a lone "\r", without the line ending.
+struct NormalizedLines<'a, R: ?Sized> {
+	reader: &'a mut R,
+}
+
+impl<'a, R: ?Sized + BufRead> Iterator for NormalizedLines<'a, R> {
+	type Item = io::Result<String>;
+	fn next(&mut self) -> Option<Self::Item> {
+		let mut line = vec![];
+		let mut eof = true;
+		loop {
+			let buf = match self.reader.fill_buf() {
+				Ok(buf) => buf,
+				Err(e) => return Some(Err(e)),
+			};
+			if buf.is_empty() {
+				break;
+			}
+			eof = false;
+			match buf.iter().position(|b| *b == b'\n' || *b == b'\r') {
+				Some(i) => {
+					line.extend_from_slice(&buf[..i]);
+					let cr = buf[i] == b'\r';
+					self.reader.consume(i + 1);
+					if cr {
+						match self.reader.fill_buf() {
+							Ok(buf) if buf.first() == Some(&b'\n') => self.reader.consume(1),
+							Ok(_) => (),
+							Err(e) => return Some(Err(e)),
+						}
+					}
+					break;
+				},
+				None => {
+					line.extend_from_slice(buf);
+					let n = buf.len();
+					self.reader.consume(n);
+				},
+			}
+		}
+		if eof {
+			return None;
+		}
+		Some(String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+	}
+}
+
+/// Lines joined across `\` continuations; a line whose code, less any comment, ends in a `\`
+/// continues on the next line. The `\` and comment are replaced by a space, so positions on
+/// the first line are unchanged. Yields each joined line with the number of its first line.
+struct ContinuedLines<'a, R: ?Sized> {
+	lines: NormalizedLines<'a, R>,
+	line_count: u32,
+}
+
+impl<'a, R: ?Sized + BufRead> Iterator for ContinuedLines<'a, R> {
+	type Item = io::Result<(u32, String)>;
+	fn next(&mut self) -> Option<Self::Item> {
+		let mut continued: Option<(u32, String)> = None;
+		loop {
+			let line = match self.lines.next() {
+				Some(Ok(line)) => line,
+				Some(Err(e)) => return Some(Err(e)),
+				None => return continued.map(Ok),
+			};
+			self.line_count += 1;
+			let (line_num, mut line) = match continued.take() {
+				Some((line_num, mut joined)) => {
+					joined.push_str(&line);
+					(line_num, joined)
+				},
+				None => (self.line_count, line),
+			};
+			match strip_comment(&line).trim_end().strip_suffix('\\').map(str::len) {
+				Some(len) => {
+					line.truncate(len);
+					line.push(' ');
+					continued = Some((line_num, line));
+				},
+				None => return Some(Ok((line_num, line))),
+			}
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, clap::ValueEnum)]
+pub enum OutputFormat {
+	/// one 16 character binary string per instruction (the standard .hack format)
+	Text,
+	/// raw big-endian 16-bit words
+	Bin,
+}
+
+impl OutputFormat {
+	pub fn extension(&self) -> &'static str {
+		match self {
+			OutputFormat::Text => "hack",
+			OutputFormat::Bin => "bin",
+		}
+	}
+}
+
+/// Write encoded instructions to `bin_out` in the given output format.
+pub fn write_words<W: ?Sized + Write>(words: &[u16], format: OutputFormat, bin_out: &mut W) -> io::Result<()> {
+	for word in words {
+		match format {
+			OutputFormat::Text => writeln!(bin_out, "{:016b}", word)?,
+			OutputFormat::Bin => bin_out.write_all(&word.to_be_bytes())?,
+		}
+	}
+	bin_out.flush()
+}
+
+/// Assemble to the standard .hack text format, with the default options.
 pub fn assemble<R: ?Sized, W: ?Sized>(asm_in: &mut R, bin_out: &mut W) -> io::Result<(u32, u16)>
 	where R: BufRead, W: Write
 {
-	const MAX_PARSE_ERRORS: u32 = 10;
+	let (line_count, ins_count, _, words) = assemble_words(asm_in, &AssembleOptions::default())?;
+	write_words(&words, OutputFormat::Text, bin_out)?;
+	Ok((line_count, ins_count))
+}
 
+/// Parse and encode all instructions in a single pass, independent of any output format.
+/// Returns the line count, instruction count, error count and encoded instructions; the
+/// instructions are empty if there were any errors.
+pub fn assemble_words<R: ?Sized>(asm_in: &mut R, options: &AssembleOptions) -> io::Result<(u32, u16, u32, Vec<u16>)>
+	where R: BufRead
+{
+	let program = assemble_program(asm_in, options)?;
+	Ok((program.line_count, program.ins_count, program.error_count, program.words))
+}
+
+/// As [`assemble_words`], also returning the resolved symbols and, if requested, the source
+/// of each line.
+pub fn assemble_program<R: ?Sized>(asm_in: &mut R, options: &AssembleOptions) -> io::Result<Program>
+	where R: BufRead
+{
 	let mut sym_key_table = HashMap::new();
 	let mut sym_val_table = vec![];
+	let mut sym_refs = HashMap::new();
 
 	let mut error_count = 0u32;
 	let mut line_count = 0u32;
@@ -95,7 +358,7 @@ pub fn assemble<R: ?Sized, W: ?Sized>(asm_in: &mut R, bin_out: &mut W) -> io::Re
 	}
 
 	for (ram_address, sym) in ["SP", "LCL", "ARG", "THIS", "THAT"].iter().enumerate() {
-		sym_key_table.insert(format!("{}", sym), sym_val_table.len());
+		sym_key_table.insert(sym.to_string(), sym_val_table.len());
 		sym_val_table.push((ram_address as u16, SymUse::ARAM));
 	}
 
@@ -109,36 +372,141 @@ pub fn assemble<R: ?Sized, W: ?Sized>(asm_in: &mut R, bin_out: &mut W) -> io::Re
 	sym_key_table.insert("KBD".to_string(), sym_val_table.len());
 	sym_val_table.push((KBD_RAM_ADDRESS, SymUse::ARAM));
 
+	let predefined_count = sym_val_table.len();
+
 	// Parse all instructions into memory...
 
+	let failed = |line_count, ins_count, error_count| Program{line_count, ins_count, error_count, ..Program::default()};
+
 	let mut inss = vec![];
-	for line_result in asm_in.lines() {
-		line_count += 1;
-		let line = line_result?;
-		match parse_ins(&line, ins_ptr, &mut sym_key_table, &mut sym_val_table){
-			Ok(Some(ins @ Ins::L1{..})) => {
-				inss.push(ins);
+	let mut lines_out = vec![];
+	let mut conditionals = Conditionals::new(&options.defines);
+	let mut macros = Macros::new();
+	let mut linkage = Linkage::new(options.object);
+	let mut lines = ContinuedLines{lines: NormalizedLines{reader: asm_in}, line_count: 0};
+	while let Some(line_result) = lines.next() {
+		line_count = lines.line_count;
+		let (line_num, line) = line_result?;
+		let expansion = match conditionals.filter(&line, line_num) {
+			Ok(true) => macros.expand(&line, line_num).map_err(|e| write_macro_error(&e, &line, line_num, ins_ptr)),
+			Ok(false) => continue,
+			Err(e) => {
+				write_directive_error(&e, &line, line_num, ins_ptr);
+				Err(())
 			},
-			Ok(Some(ins)) => {
-				inss.push(ins);
-				ins_ptr += 1;
+		};
+		let expansion = match expansion {
+			Ok(expansion) => expansion.unwrap_or_else(|| vec![line]),
+			Err(()) => {
+				error_count += 1;
+				if options.max_errors.is_some_and(|max_errors| error_count >= max_errors) {
+					write_max_errors_error(error_count);
+					return Ok(failed(line_count, ins_ptr, error_count));
+				}
+				continue;
 			},
-			Ok(None) => {
-				continue; // skip comment and whitespace lines
+		};
+		for line in expansion {
+			match linkage.filter(&line, line_num) {
+				Ok(true) => continue,
+				Ok(false) => (),
+				Err(e) => {
+					write_directive_error(&e, &line, line_num, ins_ptr);
+					error_count += 1;
+					if options.max_errors.is_some_and(|max_errors| error_count >= max_errors) {
+						write_max_errors_error(error_count);
+						return Ok(failed(line_count, ins_ptr, error_count));
+					}
+					continue;
+				},
+			}
+			let line_ptr = ins_ptr;
+			let mut sym_ids = vec![];
+			for result in parse_line(&line, ins_ptr, &mut sym_key_table, &mut sym_val_table) {
+				match result {
+					Ok(Spanned{value: ins @ Ins::L1{sym_id}, ..}) => {
+						sym_ids.push(sym_id);
+						inss.push(ins);
+					},
+					Ok(Spanned{value: ins, ..}) => {
+						if let Ins::A2{sym_id} = ins {
+							sym_refs.entry(sym_id).or_insert_with(|| SymRef::new(&line, line_num, ins_ptr)).count += 1;
+							sym_ids.push(sym_id);
+						}
+						inss.push(ins);
+						ins_ptr += 1;
+					},
+					Err(e) => {
+						write_parse_error(&e.value, &line, line_num, ins_ptr);
+						error_count += 1;
+						ins_ptr += 1;
+						if options.max_errors.is_some_and(|max_errors| error_count >= max_errors) {
+							write_max_errors_error(error_count);
+							return Ok(failed(line_count, ins_ptr, error_count));
+						}
+					},
+				}
+				if ins_ptr >= MAX_ROM_ADDRESS {
+					write_rom_exhausted_error(&line, line_num, ins_ptr);
+					return Ok(failed(line_count, ins_ptr, error_count + 1));
+				}
+			}
+			if options.listing {
+				sym_ids.dedup();
+				lines_out.push(SourceLine{line_num, text: line.clone(), rom_address: line_ptr, ins_count: ins_ptr - line_ptr, sym_ids});
+			}
+		}
+	}
+
+	if let Err(e) = conditionals.finish() {
+		write_directive_error(&e, "", 0, ins_ptr);
+		error_count += 1;
+	}
+
+	if let Err(e) = macros.finish() {
+		write_macro_error(&e, "", 0, ins_ptr);
+		error_count += 1;
+	}
+
+	// Check linkage of globals and externs...
+
+	let is_global = |sym: &str| linkage.globals.iter().any(|decl| decl.sym == sym);
+	let mut extern_ids = HashSet::new();
+	for decl in &linkage.externs {
+		match sym_key_table.get(&decl.sym) {
+			Some(&sym_id) if sym_id < predefined_count || sym_val_table[sym_id].1 == SymUse::LROM || is_global(&decl.sym) => {
+				write_directive_error(&DirectiveError::ExternDefined, &decl.line, decl.line_num, ins_ptr);
+				error_count += 1;
 			},
-			Err(e) => {
-				write_parse_error(&e, &line, line_count, ins_ptr);
+			Some(&sym_id) => {
+				extern_ids.insert(sym_id);
+			},
+			None => (),
+		}
+	}
+	for decl in &linkage.globals {
+		match sym_key_table.get(&decl.sym) {
+			Some(&sym_id) if sym_id < predefined_count => {
+				write_directive_error(&DirectiveError::GlobalPredefined, &decl.line, decl.line_num, ins_ptr);
 				error_count += 1;
-				ins_ptr += 1;
-				if error_count >= MAX_PARSE_ERRORS {
-					return Ok((line_count, ins_ptr));
-				}
+			},
+			Some(_) => (),
+			None => {
+				sym_key_table.insert(decl.sym.clone(), sym_val_table.len());
+				sym_val_table.push((DEFAULT_RAM_ADDRESS, SymUse::ARAM));
 			},
 		}
-		if ins_ptr >= MAX_ROM_ADDRESS {
-			write_rom_exhausted_error(&line, line_count, ins_ptr);
-			bin_out.flush()?;
-			return Ok((line_count, ins_ptr));
+	}
+	sym_refs.retain(|sym_id, _| !extern_ids.contains(sym_id));
+
+	// Lint variables which look like misspelt labels...
+
+	let severity = if options.warnings_as_errors { Severity::Error } else { Severity::Warning };
+	let sym_names: HashMap<usize, &str> = sym_key_table.iter().map(|(sym, sym_id)| (*sym_id, sym.as_str())).collect();
+	for (sym_id, label) in find_probable_typos(&sym_key_table, &sym_val_table, &sym_refs) {
+		write_probable_typo_warning(severity, sym_names[&sym_id], label, &sym_refs[&sym_id]);
+		if severity == Severity::Error {
+			error_count += 1;
 		}
 	}
 
@@ -151,21 +519,57 @@ pub fn assemble<R: ?Sized, W: ?Sized>(asm_in: &mut R, bin_out: &mut W) -> io::Re
 		}
 		if next_var_ram_address >= SCR_RAM_ADDRESS {
 			write_ram_exhausted_error();
-			bin_out.flush()?;
-			return Ok((line_count, ins_ptr));
+			return Ok(failed(line_count, ins_ptr, error_count + 1));
 		}
 	}
 
-	// Encode instructions and write to disk...
+	if error_count > 0 {
+		return Ok(failed(line_count, ins_ptr, error_count));
+	}
+
+	// Encode instructions...
+
+	let words = inss.iter().filter_map(|ins| encode_ins(ins, &sym_val_table)).collect();
+
+	let mut names = vec![String::new(); sym_val_table.len()];
+	for (sym, &sym_id) in &sym_key_table {
+		names[sym_id] = sym.clone();
+	}
+	let symbols: Vec<Symbol> = names.into_iter().zip(sym_val_table)
+		.map(|(name, (address, usage))| Symbol{name, address, usage})
+		.collect();
+
+	// Relocate instructions of objects...
 
-	for ins in inss {
-		if let Some(bin_ins) = encode_ins(&ins, &sym_val_table) {
-			writeln!(bin_out, "{:016b}", bin_ins)?;
+	let mut relocs = vec![];
+	if options.object {
+		let inss = inss.iter().filter(|ins| !matches!(ins, Ins::L1{..}));
+		for ins in inss {
+			relocs.push(match ins {
+				Ins::A3{..} => Reloc::Rom,
+				Ins::A2{sym_id} if *sym_id >= predefined_count => {
+					let sym = &symbols[*sym_id];
+					match sym.usage {
+						_ if extern_ids.contains(sym_id) => Reloc::Global(sym.name.clone()),
+						SymUse::LROM => Reloc::Rom,
+						SymUse::ARAM if is_global(&sym.name) => Reloc::Global(sym.name.clone()),
+						SymUse::ARAM => Reloc::Var(sym.name.clone()),
+					}
+				},
+				_ => Reloc::Abs,
+			});
+		}
+	}
+	let mut globals: Vec<Symbol> = vec![];
+	for decl in &linkage.globals {
+		if globals.iter().all(|sym| sym.name != decl.sym) {
+			let sym = &symbols[sym_key_table[&decl.sym]];
+			let address = if sym.usage == SymUse::LROM { sym.address } else { 0 };
+			globals.push(Symbol{name: sym.name.clone(), address, usage: sym.usage});
 		}
 	}
 
-	bin_out.flush()?;
-	Ok((line_count, ins_ptr))
+	Ok(Program{line_count, ins_count: ins_ptr, error_count, words, lines: lines_out, symbols, relocs, globals})
 }
 
 #[cfg(test)]
@@ -217,4 +621,151 @@ mod tests {
 			test_assemble_program(&asm_file, &bin_file);
 		}
 	}
+
+	#[test]
+	fn test_assemble_options(){
+		let asm = "@1\nD=X\nD=Y\nD=Z\n(LOOP)\n@LOPP\n0;JMP\n";
+		let assemble_with = |options: AssembleOptions| {
+			let (_, _, error_count, words) = assemble_words(&mut Cursor::new(asm), &options).unwrap();
+			(error_count, words.len())
+		};
+
+		// Assembly should stop at the error limit, or report every error without one.
+		assert_eq!(assemble_with(AssembleOptions{max_errors: Some(2), ..AssembleOptions::default()}), (2, 0));
+		assert_eq!(assemble_with(AssembleOptions{max_errors: None, ..AssembleOptions::default()}), (3, 0));
+
+		// Warnings should only fail assembly when treated as errors.
+		let asm = "(LOOP)\n@LOPP\n0;JMP\n";
+		let (_, _, error_count, words) = assemble_words(&mut Cursor::new(asm), &AssembleOptions::default()).unwrap();
+		assert_eq!((error_count, words.len()), (0, 2));
+		let options = AssembleOptions{warnings_as_errors: true, ..AssembleOptions::default()};
+		let (_, _, error_count, words) = assemble_words(&mut Cursor::new(asm), &options).unwrap();
+		assert_eq!((error_count, words.len()), (1, 0));
+	}
+
+	#[test]
+	fn test_line_endings(){
+		let lines = |text: &str| {
+			let mut reader = BufReader::with_capacity(2, Cursor::new(text.to_string()));
+			NormalizedLines{reader: &mut reader}.map(|l| l.unwrap()).collect::<Vec<_>>()
+		};
+		assert_eq!(lines("@1\nD=A\n"), vec!["@1", "D=A"]);
+		assert_eq!(lines("@1\r\nD=A\r\n"), vec!["@1", "D=A"]);
+		assert_eq!(lines("@1\rD=A\r"), vec!["@1", "D=A"]);
+		assert_eq!(lines("@1\r\rD=A"), vec!["@1", "", "D=A"]);
+		assert_eq!(lines(""), Vec::<String>::new());
+
+		// Each line ending should assemble the same program.
+		let assemble_text = |text: &str| assemble_words(&mut Cursor::new(text), &AssembleOptions::default()).unwrap();
+		assert_eq!(assemble_text("@1\r\nD=A\r\n"), assemble_text("@1\nD=A\n"));
+		assert_eq!(assemble_text("@1\rD=A\r"), assemble_text("@1\nD=A\n"));
+	}
+
+	#[test]
+	fn test_multiple_ins_per_line(){
+		let assemble_text = |text: &str| {
+			let (_, ins_count, error_count, words) = assemble_words(&mut Cursor::new(text), &AssembleOptions::default()).unwrap();
+			(ins_count, error_count, words)
+		};
+		let expected = assemble_text("(LOOP)\n@LOOP\nD=A\nD;JGT\n@5\nM=D\n0;JMP\n");
+		assert_eq!(expected.1, 0);
+
+		// Separated and continued instructions should assemble as if one per line.
+		assert_eq!(assemble_text("(LOOP); @LOOP; D=A\nD;JGT; @5; M=D; 0;JMP\n"), expected);
+		assert_eq!(assemble_text("(LOOP)\n@LO\\\nOP\nD=A; D;\\  \n JGT\n@5\nM=D\n0;JMP\\"), expected);
+
+		// Continued lines should count towards the line count.
+		let lines = |text: &str| {
+			let mut reader = Cursor::new(text);
+			let mut lines = ContinuedLines{lines: NormalizedLines{reader: &mut reader}, line_count: 0};
+			let joined = lines.by_ref().map(|l| l.unwrap()).collect::<Vec<_>>();
+			(joined, lines.line_count)
+		};
+		assert_eq!(lines("@1\\\n2\n\\\n\\\nD=A"), (vec![(1, "@1 2".to_string()), (3, "  D=A".to_string())], 5));
+
+		// A '\' ending a comment should not continue the line.
+		assert_eq!(assemble_text("@1 // C:\\\nD=A\n@2\n").0, 3);
+		assert_eq!(lines("@1 \\ // more\nD=A"), (vec![(1, "@1  D=A".to_string())], 2));
+	}
+
+	#[test]
+	fn test_conditional_assembly(){
+		let asm = ".if DEBUG\n@1\nD=A\n.else\n@2\n.endif\nD;JGT\n";
+		let assemble_with = |defines: &[&str]| {
+			let options = AssembleOptions{defines: defines.iter().map(|d| d.to_string()).collect(), ..AssembleOptions::default()};
+			let (_, _, error_count, words) = assemble_words(&mut Cursor::new(asm), &options).unwrap();
+			(error_count, words)
+		};
+
+		// Only the blocks whose condition holds should be assembled.
+		assert_eq!(assemble_with(&["DEBUG"]), (0, vec![1, 0b1110_1100_0001_0000, 0b1110_0011_0000_0001]));
+		assert_eq!(assemble_with(&[]), (0, vec![2, 0b1110_0011_0000_0001]));
+
+		// Malformed directives should be errors, including a block left open.
+		let (_, _, error_count, _) = assemble_words(&mut Cursor::new(".else\n.if A\n@1\n"), &AssembleOptions::default()).unwrap();
+		assert_eq!(error_count, 2);
+	}
+
+	#[test]
+	fn test_macro_expansion(){
+		let asm = ".macro PUSH x\n@x\nD=A\n@SP\nAM=M+1\nA=A-1\nM=D\n.endmacro\n.if DEBUG\nPUSH 1\n.endif\nPUSH 2\n";
+		let (_, ins_count, error_count, words) = assemble_words(&mut Cursor::new(asm), &AssembleOptions::default()).unwrap();
+		assert_eq!((ins_count, error_count), (6, 0));
+		assert_eq!(words[0], 2);
+
+		// Conditionals within a body should be evaluated once, as the definition is read, even around its directives.
+		let asm = ".macro LOG\n.if DEBUG\n@1\n.else\n@2\n.endif\n.endmacro\nLOG\nLOG\n";
+		let options = AssembleOptions{defines: vec!["DEBUG".to_string()].into_iter().collect(), ..AssembleOptions::default()};
+		let (_, _, error_count, words) = assemble_words(&mut Cursor::new(asm), &options).unwrap();
+		assert_eq!((error_count, words), (0, vec![1, 1]));
+		let (_, _, error_count, words) = assemble_words(&mut Cursor::new(asm), &AssembleOptions::default()).unwrap();
+		assert_eq!((error_count, words), (0, vec![2, 2]));
+		let asm = ".macro M\n@1\n.if DEBUG\n.endmacro\n.endif\n@2\n.endmacro\nM\n";
+		let (_, _, error_count, words) = assemble_words(&mut Cursor::new(asm), &AssembleOptions::default()).unwrap();
+		assert_eq!((error_count, words), (0, vec![1, 2]));
+
+		// Errors within an expansion should be reported, as should malformed invocations.
+		let (_, _, error_count, _) = assemble_words(&mut Cursor::new(".macro BAD x\n@x\nD+1\n.endmacro\nBAD 1\nBAD\n"),
+			&AssembleOptions::default()).unwrap();
+		assert_eq!(error_count, 2);
+	}
+
+	#[test]
+	fn test_linkage_errors(){
+		let error_count = |asm: &str, object: bool| {
+			let options = AssembleOptions{object, ..AssembleOptions::default()};
+			assemble_program(&mut Cursor::new(asm), &options).unwrap().error_count
+		};
+
+		// Globals need not be linked; externs must be.
+		assert_eq!(error_count(".global main\n(main)\n@main\n0;JMP\n", false), 0);
+		assert_eq!(error_count(".extern Sys.init\n@Sys.init\n0;JMP\n", false), 1);
+		assert_eq!(error_count(".extern Sys.init\n@Sys.init\n0;JMP\n", true), 0);
+
+		// Externs should not be defined, nor globals predefined.
+		assert_eq!(error_count(".extern main\n(main)\n", true), 1);
+		assert_eq!(error_count(".extern i\n.global i\n@i\n", true), 1);
+		assert_eq!(error_count(".extern SP\n@SP\n", true), 1);
+		assert_eq!(error_count(".global SCREEN\n@1\n", true), 1);
+	}
+
+	#[test]
+	fn test_pos_pointer(){
+		assert_eq!(pos_pointer(2, 0, "(-)"), "  ^");
+		assert_eq!(pos_pointer(2, 1, "(-)"), "  ~^");
+		assert_eq!(pos_pointer(0, 3, "\t\t@-"), "\t\t~^");
+	}
+
+	#[test]
+	fn test_write_words_formats(){
+		let words = [0b0000_0000_0000_0010u16, 0b1110_1100_0001_0000u16];
+
+		let mut text = vec![];
+		write_words(&words, OutputFormat::Text, &mut text).unwrap();
+		assert_eq!(String::from_utf8(text).unwrap(), "0000000000000010\n1110110000010000\n");
+
+		let mut bin = vec![];
+		write_words(&words, OutputFormat::Bin, &mut bin).unwrap();
+		assert_eq!(bin, vec![0x00, 0x02, 0xEC, 0x10]);
+	}
 }
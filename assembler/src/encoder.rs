@@ -9,91 +9,119 @@ use crate::parser::{Ins, DestMne, CompMne, JumpMne, SymUse};
 // d = dest bits
 // j = jump bits
 
+// Declarative tables of the Hack ISA; the single source of truth for both encoding and
+// decoding. Where several mnemonics share an encoding the canonical mnemonic, the one
+// produced by decoding, is listed first.
+
+/// Dest mnemonics and their ddd bits.
+pub const DEST_TABLE: &[(DestMne, u16)] = &[
+	(DestMne::DestM,   0b001),
+	(DestMne::DestD,   0b010),
+	(DestMne::DestMD,  0b011),
+	(DestMne::DestDM,  0b011),
+	(DestMne::DestA,   0b100),
+	(DestMne::DestAM,  0b101),
+	(DestMne::DestMA,  0b101),
+	(DestMne::DestAD,  0b110),
+	(DestMne::DestDA,  0b110),
+	(DestMne::DestAMD, 0b111),
+	(DestMne::DestADM, 0b111),
+	(DestMne::DestDAM, 0b111),
+	(DestMne::DestDMA, 0b111),
+	(DestMne::DestMAD, 0b111),
+	(DestMne::DestMDA, 0b111),
+];
+
+/// Comp mnemonics and their a cccccc bits.
+pub const COMP_TABLE: &[(CompMne, u16)] = &[
+	(CompMne::Comp0,       0b0_101010),
+	(CompMne::Comp1,       0b0_111111),
+	(CompMne::CompMinus1,  0b0_111010),
+	(CompMne::CompD,       0b0_001100),
+	(CompMne::CompA,       0b0_110000),
+	(CompMne::CompM,       0b1_110000),
+	(CompMne::CompNotD,    0b0_001101),
+	(CompMne::CompNotA,    0b0_110001),
+	(CompMne::CompNotM,    0b1_110001),
+	(CompMne::CompMinusD,  0b0_001111),
+	(CompMne::CompMinusA,  0b0_110011),
+	(CompMne::CompMinusM,  0b1_110011),
+	(CompMne::CompDPlus1,  0b0_011111),
+	(CompMne::Comp1PlusD,  0b0_011111),
+	(CompMne::CompAPlus1,  0b0_110111),
+	(CompMne::Comp1PlusA,  0b0_110111),
+	(CompMne::CompMPlus1,  0b1_110111),
+	(CompMne::Comp1PlusM,  0b1_110111),
+	(CompMne::CompDMinus1, 0b0_001110),
+	(CompMne::CompAMinus1, 0b0_110010),
+	(CompMne::CompMMinus1, 0b1_110010),
+	(CompMne::CompDPlusA,  0b0_000010),
+	(CompMne::CompAPlusD,  0b0_000010),
+	(CompMne::CompDPlusM,  0b1_000010),
+	(CompMne::CompMPlusD,  0b1_000010),
+	(CompMne::CompDMinusA, 0b0_010011),
+	(CompMne::CompDMinusM, 0b1_010011),
+	(CompMne::CompAMinusD, 0b0_000111),
+	(CompMne::CompMMinusD, 0b1_000111),
+	(CompMne::CompDAndA,   0b0_000000),
+	(CompMne::CompAAndD,   0b0_000000),
+	(CompMne::CompDAndM,   0b1_000000),
+	(CompMne::CompMAndD,   0b1_000000),
+	(CompMne::CompDOrA,    0b0_010101),
+	(CompMne::CompAOrD,    0b0_010101),
+	(CompMne::CompDOrM,    0b1_010101),
+	(CompMne::CompMOrD,    0b1_010101),
+];
+
+/// Jump mnemonics and their jjj bits.
+pub const JUMP_TABLE: &[(JumpMne, u16)] = &[
+	(JumpMne::JumpJgt, 0b001),
+	(JumpMne::JumpJeq, 0b010),
+	(JumpMne::JumpJge, 0b011),
+	(JumpMne::JumpJlt, 0b100),
+	(JumpMne::JumpJne, 0b101),
+	(JumpMne::JumpJle, 0b110),
+	(JumpMne::JumpJmp, 0b111),
+];
+
+const C_INS_FMT: u16 = 0b111_0_000000_000_000;
+const DEST_SHIFT: u16 = 3;
+const COMP_SHIFT: u16 = 6;
+const DEST_MASK: u16 = 0b111;
+const COMP_MASK: u16 = 0b1_111111;
+const JUMP_MASK: u16 = 0b111;
+
+fn table_bits<M: PartialEq>(table: &[(M, u16)], mne: &M) -> u16 {
+	table.iter().find(|(m, _)| m == mne).map(|(_, bits)| *bits).expect("mnemonic missing from ISA table")
+}
+
+fn table_mne<M: Copy>(table: &[(M, u16)], bits: u16) -> Option<M> {
+	table.iter().find(|(_, b)| *b == bits).map(|(m, _)| *m)
+}
+
 impl DestMne {
 	fn as_u16(&self) -> u16 {
-		match self {
-			DestMne::DestM   => 0b111_0_000000_001_000,
-			DestMne::DestD   => 0b111_0_000000_010_000,
-			DestMne::DestA   => 0b111_0_000000_100_000,
-			DestMne::DestDM  => 0b111_0_000000_011_000,
-			DestMne::DestMD  => 0b111_0_000000_011_000,
-			DestMne::DestAM  => 0b111_0_000000_101_000,
-			DestMne::DestMA  => 0b111_0_000000_101_000,
-			DestMne::DestAD  => 0b111_0_000000_110_000,
-			DestMne::DestDA  => 0b111_0_000000_110_000,
-			DestMne::DestADM => 0b111_0_000000_111_000,
-			DestMne::DestAMD => 0b111_0_000000_111_000,
-			DestMne::DestDAM => 0b111_0_000000_111_000,
-			DestMne::DestDMA => 0b111_0_000000_111_000,
-			DestMne::DestMAD => 0b111_0_000000_111_000,
-			DestMne::DestMDA => 0b111_0_000000_111_000,
-		}
+		C_INS_FMT | table_bits(DEST_TABLE, self) << DEST_SHIFT
 	}
 }
 
 impl CompMne {
 	fn as_u16(&self) -> u16 {
-		match self {
-			CompMne::Comp0       => 0b111_0_101010_000_000,
-			CompMne::Comp1       => 0b111_0_111111_000_000,
-			CompMne::CompMinus1  => 0b111_0_111010_000_000,
-			CompMne::CompD       => 0b111_0_001100_000_000,
-			CompMne::CompA       => 0b111_0_110000_000_000,
-			CompMne::CompM       => 0b111_1_110000_000_000,
-			CompMne::CompNotD    => 0b111_0_001101_000_000,
-			CompMne::CompNotA    => 0b111_0_110001_000_000,
-			CompMne::CompNotM    => 0b111_1_110001_000_000,
-			CompMne::CompMinusD  => 0b111_0_001111_000_000,
-			CompMne::CompMinusA  => 0b111_0_110011_000_000,
-			CompMne::CompMinusM  => 0b111_1_110011_000_000,
-			CompMne::CompDPlus1  => 0b111_0_011111_000_000,
-			CompMne::CompAPlus1  => 0b111_0_110111_000_000,
-			CompMne::CompMPlus1  => 0b111_1_110111_000_000,
-			CompMne::Comp1PlusD  => 0b111_0_011111_000_000,
-			CompMne::Comp1PlusA  => 0b111_0_110111_000_000,
-			CompMne::Comp1PlusM  => 0b111_1_110111_000_000,
-			CompMne::CompDMinus1 => 0b111_0_001110_000_000,
-			CompMne::CompAMinus1 => 0b111_0_110010_000_000,
-			CompMne::CompMMinus1 => 0b111_1_110010_000_000,
-			CompMne::CompDPlusA  => 0b111_0_000010_000_000,
-			CompMne::CompDPlusM  => 0b111_1_000010_000_000,
-			CompMne::CompAPlusD  => 0b111_0_000010_000_000,
-			CompMne::CompMPlusD  => 0b111_1_000010_000_000,
-			CompMne::CompDMinusA => 0b111_0_010011_000_000,
-			CompMne::CompDMinusM => 0b111_1_010011_000_000,
-			CompMne::CompAMinusD => 0b111_0_000111_000_000,
-			CompMne::CompMMinusD => 0b111_1_000111_000_000,
-			CompMne::CompDAndA   => 0b111_1_000000_000_000,
-			CompMne::CompDAndM   => 0b111_1_000000_000_000,
-			CompMne::CompAAndD   => 0b111_1_000000_000_000,
-			CompMne::CompMAndD   => 0b111_1_000000_000_000,
-			CompMne::CompDOrA    => 0b111_0_010101_000_000,
-			CompMne::CompDOrM    => 0b111_1_010101_000_000,
-			CompMne::CompAOrD    => 0b111_0_010101_000_000,
-			CompMne::CompMOrD    => 0b111_1_010101_000_000,
-		}
+		C_INS_FMT | table_bits(COMP_TABLE, self) << COMP_SHIFT
 	}
 }
 
 impl JumpMne {
 	fn as_u16(&self) -> u16 {
-		match self {
-			JumpMne::JumpJgt => 0b111_0_000000_000_001,
-			JumpMne::JumpJeq => 0b111_0_000000_000_010,
-			JumpMne::JumpJge => 0b111_0_000000_000_011,
-			JumpMne::JumpJlt => 0b111_0_000000_000_100,
-			JumpMne::JumpJne => 0b111_0_000000_000_101,
-			JumpMne::JumpJle => 0b111_0_000000_000_110,
-			JumpMne::JumpJmp => 0b111_0_000000_000_111,
-		}
+		C_INS_FMT | table_bits(JUMP_TABLE, self)
 	}
 }
 
 const A_INS_FMT: u16 = 0b0_111111111111111;
 
-pub fn encode_ins(ins: &Ins, sym_val_table: &Vec<(u16, SymUse)>) -> Option<u16> {
+pub fn encode_ins(ins: &Ins, sym_val_table: &[(u16, SymUse)]) -> Option<u16> {
 	match ins {
-		Ins::A1{cint} => {
+		Ins::A1{cint} | Ins::A3{cint} => {
 			Some(A_INS_FMT & cint)
 		},
 		Ins::A2{sym_id} => {
@@ -113,3 +141,87 @@ pub fn encode_ins(ins: &Ins, sym_val_table: &Vec<(u16, SymUse)>) -> Option<u16>
 		},
 	}
 }
+
+/// Decode a binary instruction to canonical mnemonics. Returns None for words which are not
+/// valid Hack instructions, and for C-instructions with neither a dest nor a jump, which
+/// have no effect and so cannot be assembled.
+pub fn decode_ins(word: u16) -> Option<Ins> {
+	if word & !A_INS_FMT == 0 {
+		return Some(Ins::A1{cint: word});
+	}
+	if word & C_INS_FMT != C_INS_FMT {
+		return None;
+	}
+	let comp = table_mne(COMP_TABLE, (word >> COMP_SHIFT) & COMP_MASK)?;
+	let dest = (word >> DEST_SHIFT) & DEST_MASK;
+	let jump = word & JUMP_MASK;
+	match (table_mne(DEST_TABLE, dest), table_mne(JUMP_TABLE, jump)) {
+		(Some(dest), Some(jump)) => Some(Ins::C2{dest, comp, jump}),
+		(Some(dest), None) => Some(Ins::C1{dest, comp}),
+		(None, Some(jump)) => Some(Ins::C3{comp, jump}),
+		(None, None) => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use enum_iterator::all;
+	use super::*;
+
+	#[test]
+	fn test_isa_tables_are_complete(){
+		for dest in all::<DestMne>() {
+			assert_eq!(DEST_TABLE.iter().filter(|(m, _)| *m == dest).count(), 1, "{:?}", dest);
+		}
+		for comp in all::<CompMne>() {
+			assert_eq!(COMP_TABLE.iter().filter(|(m, _)| *m == comp).count(), 1, "{:?}", comp);
+		}
+		for jump in all::<JumpMne>() {
+			assert_eq!(JUMP_TABLE.iter().filter(|(m, _)| *m == jump).count(), 1, "{:?}", jump);
+		}
+	}
+
+	#[test]
+	fn test_encode_decode_round_trip(){
+		let sym_val_table = [];
+
+		// Every C-instruction should decode to mnemonics which encode back to the same word.
+		for comp in all::<CompMne>() {
+			for dest in all::<DestMne>() {
+				for jump in all::<JumpMne>() {
+					for ins in [Ins::C1{dest, comp}, Ins::C2{dest, comp, jump}, Ins::C3{comp, jump}] {
+						let word = encode_ins(&ins, &sym_val_table).unwrap();
+						let decoded = decode_ins(word).unwrap();
+						assert_eq!(encode_ins(&decoded, &sym_val_table), Some(word), "{:?}", ins);
+					}
+				}
+			}
+		}
+
+		// Every decodable word should encode back to itself.
+		let mut c_ins_count = 0;
+		for word in 0..=u16::MAX {
+			if let Some(ins) = decode_ins(word) {
+				assert_eq!(encode_ins(&ins, &sym_val_table), Some(word), "{:016b}", word);
+				c_ins_count += (word >> 15) as u32;
+			}
+		}
+
+		// 28 distinct comp encodings, each with 8x8 dest/jump pairs less the nop pair.
+		assert_eq!(c_ins_count, 28 * (8 * 8 - 1));
+
+		assert_eq!(decode_ins(0b1110_0011_0000_0000), None); // no effect
+		assert_eq!(decode_ins(0b1111_1111_1101_0000), None); // unknown comp
+		assert_eq!(decode_ins(0b1000_1100_0001_0000), None); // prefix bits clear
+	}
+
+	#[test]
+	fn test_canonical_decoding(){
+		let decode = |word| decode_ins(word).unwrap();
+		assert_eq!(decode(0b1110_0000_0001_0000), Ins::C1{dest: DestMne::DestD, comp: CompMne::CompDAndA});
+		assert_eq!(decode(0b1111_0000_0001_0000), Ins::C1{dest: DestMne::DestD, comp: CompMne::CompDAndM});
+		assert_eq!(decode(0b1110_0111_1101_1000), Ins::C1{dest: DestMne::DestMD, comp: CompMne::CompDPlus1});
+		assert_eq!(decode(0b1110_1010_1000_0111), Ins::C3{comp: CompMne::Comp0, jump: JumpMne::JumpJmp});
+		assert_eq!(decode(0b0000_0000_0001_0001), Ins::A1{cint: 17});
+	}
+}
@@ -0,0 +1,301 @@
+pub const ROM_EXHAUSTED_CODE: &'static str = "E014";
+pub const RAM_EXHAUSTED_CODE: &'static str = "E015";
+pub const DIRECTIVE_CODE: &'static str = "E016";
+pub const MACRO_CODE: &'static str = "E021";
+pub const PROBABLE_TYPO_CODE: &'static str = "W001";
+
+/// Extended descriptions of every error and warning code reported by the assembler, printed by `--explain`.
+pub const ERROR_CATALOGUE: &[(&'static str, &'static str)] = &[
+	("E001", "\
+Unknown mnemonic.
+
+A C-instruction has the form `dest=comp;jump` where either the dest or the jump part
+may be omitted. Each part must be one of the mnemonics defined by the Hack language;
+anything else is rejected. A mnemonic longer than three characters at the start of a
+line cannot be attributed to a part and is reported without one.
+
+    D=M+2     // error: comp 'M+2' is unknown; only the constants 0, 1 and -1 exist
+    X=D       // error: dest 'X' is unknown
+    D;JUMP    // error: jump 'JUMP' is unknown
+
+Fix the mnemonic, or load the constant into A first:
+
+    @2
+    D=A
+"),
+	("E002", "\
+Expected a valid first symbol character.
+
+Symbols may contain letters, digits, '_', '.', '$' and ':' but may not start with a
+digit. An A-instruction argument starting with a digit is parsed as an integer.
+
+    (1loop)   // error: labels cannot start with a digit
+    @-1       // error: negative constants are not supported
+
+Rename the symbol so it starts with a letter or one of '_', '.', '$', ':'.
+"),
+	("E003", "\
+Expected a valid symbol character.
+
+A symbol contained a character which is not a letter, digit, '_', '.', '$' or ':'.
+
+    @loop-end   // error: '-' is not a symbol character
+
+Remove or replace the offending character.
+"),
+	("E004", "\
+Expected a digit.
+
+An A-instruction argument which starts with a digit is an integer constant and may only
+contain digits; hex digits after `0x`, or binary digits after `0b`.
+
+    @4foo     // error: 'f' is not a digit
+    @0x1G     // error: 'G' is not a hex digit
+    @0b102    // error: '2' is not a binary digit
+
+Symbols must start with a non-digit character: use `@foo4` instead.
+"),
+	("E005", "\
+Unexpected character.
+
+Characters were found after the closing ')' of a label declaration, or within a
+character literal after its character. A label declaration ends at its ')'; any
+instruction after it on the same line must be separated by a ';'. A character literal
+quotes a single character.
+
+    (LOOP) D=M   // error: write (LOOP); D=M
+    @'AB'        // error: one character only
+"),
+	("E006", "\
+Duplicate label definition.
+
+A label names a single ROM address, so each label may only be declared once.
+
+    (LOOP)
+    ...
+    (LOOP)    // error: LOOP is already declared
+
+Give the second label a different name.
+"),
+	("E007", "\
+A-instruction is missing its argument.
+
+An '@' must be followed by an integer constant, a symbol or a relative address.
+
+    @         // error
+    @$+       // error: expected an offset after '+'
+    @0x       // error: expected hex digits after '0x'
+    @'        // error: expected a character after the quote
+    @17       // ok
+    @LOOP     // ok
+"),
+	("E008", "\
+Label declaration is missing its symbol.
+
+A '(' must be followed by the label's symbol and a closing ')'.
+
+    (         // error
+    (LOOP)    // ok
+"),
+	("E009", "\
+Label declaration is missing its closing ')'.
+
+    (LOOP     // error
+    (LOOP)    // ok
+"),
+	("E010", "\
+Symbol too long.
+
+Symbols, and integer constants, are limited to 255 characters.
+"),
+	("E011", "\
+Integer constant too large.
+
+A-instructions load a 15-bit constant; the largest constant is 32767. To load a larger
+value, or a negative value, compute it:
+
+    @32767
+    D=A
+    D=D+1     // D = 32768 (-32768 as a signed value)
+"),
+	("E012", "\
+Non-ASCII character.
+
+Hack assembly is ASCII only; lines containing other characters, even in comments, are
+rejected.
+"),
+	("E013", "\
+C-instruction has no effect.
+
+A C-instruction with neither a dest nor a jump part computes a value and discards it.
+
+    D+1       // error
+    D=D+1     // ok
+    D;JGT     // ok
+"),
+	("E017", "\
+Relative address out of range.
+
+`@$+n` and `@$-n` load the ROM address n instructions after or before the current
+instruction. The resulting address must lie within ROM, from 0 to 32767.
+
+    @$-1      // error: as the first instruction, refers to address -1
+    @$+2      // ok: skips the next instruction
+    0;JMP
+"),
+	("E018", "\
+Hex literal too large.
+
+A-instructions load a 15-bit constant; the largest hex literal is 0x7FFF.
+
+    @0xFFFF   // error
+    @0x7FFF   // ok
+"),
+	("E019", "\
+Binary literal too large.
+
+A-instructions load a 15-bit constant; the largest binary literal has 15 digits.
+
+    @0b1000000000000000   // error
+    @0b111111111111111    // ok
+"),
+	("E020", "\
+Character literal is missing its closing quote.
+
+A character literal quotes exactly one ASCII character, and loads its code.
+
+    @'A       // error
+    @'A'      // ok: loads 65
+"),
+	(ROM_EXHAUSTED_CODE, "\
+ROM exhausted.
+
+The program has more instructions than fit in the 32K word instruction memory.
+Assembly stops at the first instruction which does not fit.
+"),
+	(RAM_EXHAUSTED_CODE, "\
+RAM exhausted.
+
+Variables are allocated consecutive RAM addresses starting at 16. The program declares
+more variables than fit below the screen memory map at address 16384.
+"),
+	(DIRECTIVE_CODE, "\
+Malformed conditional assembly or linkage directive.
+
+Lines between `.if SYMBOL` and the matching `.else` or `.endif` are only assembled
+when SYMBOL is defined on the command line with `-D SYMBOL`; lines between `.else` and
+`.endif` only when it is not. Every `.if` must be closed by an `.endif`, blocks may be
+nested, and each directive must be on a line of its own.
+
+    .if DEBUG
+    @ASSERT_FAILED
+    D;JNE
+    .endif
+
+    .ifdef DEBUG   // error: unknown directive
+    .if            // error: expected a symbol
+    .else          // error: no enclosing '.if'
+
+A `.global SYMBOL` exports a label or variable for other objects to import with
+`.extern SYMBOL`, when assembled with `--object` and linked with `--link`. A global
+which is not a label is a variable. An extern must not also be defined, and predefined
+symbols cannot be global.
+
+    .extern Sys.init
+    (Sys.init)     // error: extern defined here
+"),
+	(MACRO_CODE, "\
+Malformed macro definition or invocation.
+
+Lines between `.macro NAME PARAM...` and `.endmacro` define a macro. An instruction starting
+with the name of a macro is replaced by its body, with each parameter replaced by its
+argument; as any instruction, an invocation ends at a ';'.
+Names and parameters must be symbols, and each macro may only be defined once. Macros may
+invoke other macros, but not define them, nor invoke themselves.
+
+    .macro LOAD value, dest
+    @value
+    D=A
+    @dest
+    M=D
+    .endmacro
+
+    LOAD 5, R13    // ok
+    @1; LOAD 5, R13; D=M    // ok
+    LOAD 5         // error: expected 2 arguments
+
+Errors within an expansion are reported at the line of the invocation.
+
+Conditional assembly runs before macros, so an `.if` within a macro body is evaluated
+once, where the macro is defined, rather than at each invocation; the body holds only the
+lines whose condition held at the definition.
+"),
+	(PROBABLE_TYPO_CODE, "\
+Variable is probably a misspelt label.
+
+Any symbol which is not a label is a variable, so a misspelt jump target assembles
+without error into a jump to whatever ROM address equals the variable's RAM address.
+A variable which is referenced only once, and which differs from a label by one or two
+characters, is reported as a probable typo.
+
+    (LOOP)
+    ...
+    @LOPP     // warning: did you mean label 'LOOP'?
+    0;JMP
+"),
+];
+
+/// Extended description of an error or warning code; codes are case insensitive.
+pub fn explain(code: &str) -> Option<&'static str> {
+	ERROR_CATALOGUE.iter()
+		.find(|(c, _)| c.eq_ignore_ascii_case(code))
+		.map(|(_, text)| *text)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+	use crate::parser::{ParseError, MneType};
+	use super::*;
+
+	#[test]
+	fn test_error_codes_are_catalogued(){
+		let errors = [
+			ParseError::UnknownMne{mne_type: Some(MneType::Dest), mne_buf: [b' '; 4]},
+			ParseError::ExpectedFirstSymChar{found: ' ', pos: 0},
+			ParseError::ExpectedSymChar{found: ' ', pos: 0},
+			ParseError::ExpectedDigit{found: ' ', pos: 0},
+			ParseError::UnexpectedChar{found: ' ', pos: 0},
+			ParseError::DuplicateLabel,
+			ParseError::AInsMissingArg,
+			ParseError::LInsMissingSym,
+			ParseError::LInsMissingClose,
+			ParseError::SymOverflow,
+			ParseError::IntOverflow,
+			ParseError::NotASCII,
+			ParseError::CInsNop,
+			ParseError::RelOutOfRange,
+			ParseError::HexOverflow,
+			ParseError::BinOverflow,
+			ParseError::CharMissingClose,
+		];
+
+		// Every error should have a unique code with an explanation.
+		let mut codes = HashSet::new();
+		for e in &errors {
+			assert!(codes.insert(e.code()), "duplicate code {}", e.code());
+			assert!(explain(e.code()).is_some(), "{} has no explanation", e.code());
+		}
+		assert!(codes.insert(ROM_EXHAUSTED_CODE));
+		assert!(codes.insert(RAM_EXHAUSTED_CODE));
+		assert!(codes.insert(DIRECTIVE_CODE));
+		assert!(codes.insert(MACRO_CODE));
+		assert!(codes.insert(PROBABLE_TYPO_CODE));
+
+		// The catalogue should not document codes which are never reported.
+		assert_eq!(codes.len(), ERROR_CATALOGUE.len());
+
+		assert_eq!(explain("e006"), explain("E006"));
+		assert_eq!(explain("E999"), None);
+	}
+}
@@ -0,0 +1,125 @@
+use std::fmt::Write;
+use crate::parser::MAX_INT_VAL;
+use crate::encoder::{DEST_TABLE, COMP_TABLE, JUMP_TABLE};
+
+/// Most instructions a program may have; the size of ROM.
+pub const MAX_INS_COUNT: usize = 32768;
+
+const PREDEFINED_SYMBOLS: &[&str] = &[
+	"R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9", "R10", "R11", "R12", "R13", "R14", "R15",
+	"SP", "LCL", "ARG", "THIS", "THAT", "SCREEN", "KBD",
+];
+
+/// Options of a generated program.
+#[derive(Debug, Clone)]
+pub struct GenOptions {
+	/// Number of instructions, excluding label declarations; at most [`MAX_INS_COUNT`].
+	pub ins_count: usize,
+	/// Probability that an instruction is preceded by a label declaration.
+	pub label_density: f64,
+	/// Number of distinct variables referenced.
+	pub var_count: usize,
+	/// Programs are reproducible from their seed.
+	pub seed: u64,
+}
+
+impl Default for GenOptions {
+	fn default() -> Self {
+		GenOptions{ins_count: 1000, label_density: 0.05, var_count: 32, seed: 1}
+	}
+}
+
+/// Small xorshift generator, shared by the program generators of the toolchain; programs
+/// need only be varied and reproducible from their seed.
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		Rng(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	/// A number in `0..n`.
+	pub fn below(&mut self, n: usize) -> usize {
+		(self.next_u64() % n as u64) as usize
+	}
+
+	/// True with probability `p`.
+	pub fn chance(&mut self, p: f64) -> bool {
+		((self.next_u64() >> 11) as f64) < p * (1u64 << 53) as f64
+	}
+
+	pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+		&items[self.below(items.len())]
+	}
+}
+
+/// Generate a random valid Hack assembly program. Every label referenced is declared exactly
+/// once, every C-instruction has a dest or a jump, and the program is sprinkled with the
+/// comments, blank lines and indentation found in hand written code.
+pub fn generate_asm(options: &GenOptions) -> String {
+	assert!(options.ins_count <= MAX_INS_COUNT, "programs must fit in ROM");
+	let mut rng = Rng::new(options.seed);
+
+	// Decide label positions first so jumps may target labels declared later.
+	let label_at: Vec<bool> = (0..options.ins_count).map(|_| rng.chance(options.label_density)).collect();
+	let labels: Vec<String> = (0..label_at.iter().filter(|l| **l).count()).map(|i| format!("L{}_{}", i, rng.below(1000))).collect();
+	let vars: Vec<String> = (0..options.var_count).map(|i| format!("var{}.{}", i, rng.below(100))).collect();
+
+	let mut asm = String::new();
+	let mut next_label = 0;
+	for declare_label in label_at {
+		if declare_label {
+			writeln!(asm, "({})", labels[next_label]).unwrap();
+			next_label += 1;
+		}
+		let indent = *rng.pick(&["", "\t", "    "]);
+		let ins = match rng.below(8) {
+			0 => format!("@{}", rng.below(MAX_INT_VAL as usize + 1)),
+			1 => format!("@{}", rng.pick(PREDEFINED_SYMBOLS)),
+			2 if !vars.is_empty() => format!("@{}", rng.pick(&vars)),
+			3 if !labels.is_empty() => format!("@{}", rng.pick(&labels)),
+			_ => {
+				let comp = rng.pick(COMP_TABLE).0.as_str();
+				match rng.below(3) {
+					0 => format!("{}={}", rng.pick(DEST_TABLE).0.as_str(), comp),
+					1 => format!("{};{}", comp, rng.pick(JUMP_TABLE).0.as_str()),
+					_ => format!("{}={};{}", rng.pick(DEST_TABLE).0.as_str(), comp, rng.pick(JUMP_TABLE).0.as_str()),
+				}
+			},
+		};
+		match rng.below(10) {
+			0 => writeln!(asm, "{}{} // {}", indent, ins, rng.next_u64()).unwrap(),
+			1 => writeln!(asm, "\n{}{}", indent, ins).unwrap(),
+			_ => writeln!(asm, "{}{}", indent, ins).unwrap(),
+		}
+	}
+	asm
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+	use crate::assembler::*;
+	use crate::encoder::decode_ins;
+	use super::*;
+
+	#[test]
+	fn test_generated_programs_assemble(){
+		for seed in 0..20 {
+			let options = GenOptions{ins_count: 500, label_density: 0.1, var_count: 8, seed};
+			let asm = generate_asm(&options);
+			assert_eq!(asm, generate_asm(&options), "programs should be reproducible");
+
+			let (_, ins_count, error_count, words) = assemble_words(&mut Cursor::new(&asm), &AssembleOptions::default()).unwrap();
+			assert_eq!((error_count, ins_count as usize, words.len()), (0, 500, 500), "seed {}:\n{}", seed, asm);
+			assert!(words.iter().all(|w| decode_ins(*w).is_some()));
+		}
+	}
+}
@@ -0,0 +1,28 @@
+//! The Hack assembler as a library, for tools which assemble in-process or need the grammar
+//! of the assembler without duplicating it; e.g. emulators, test harnesses, editors and
+//! visualizers. Enable the `serde` feature to serialize parsed instructions and errors.
+//! [`generator`] generates random valid programs for property tests and benchmarks.
+//!
+//! Diagnostics are printed to stdout as by the `n2tasm` binary; [`assemble_words`] returns
+//! only their count.
+//!
+//! ```
+//! let mut asm_in = "@2\nD=A\n@3\nD=D+A\n@0\nM=D\n".as_bytes();
+//! let mut bin_out = vec![];
+//! assert_eq!(n2t_assembler::assemble(&mut asm_in, &mut bin_out).unwrap(), (6, 6));
+//! assert!(String::from_utf8(bin_out).unwrap().starts_with("0000000000000010\n"));
+//! ```
+
+pub mod parser;
+pub mod encoder;
+pub mod assembler;
+pub mod explain;
+pub mod lint;
+pub mod preprocessor;
+pub mod macros;
+pub mod listing;
+pub mod object;
+pub mod generator;
+
+pub use crate::assembler::{assemble, assemble_words, assemble_program, write_words, AssembleOptions, OutputFormat, Program};
+pub use crate::parser::{Ins, ParseError, SymUse, SymKeyTable, SymValTable};
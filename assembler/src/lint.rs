@@ -0,0 +1,113 @@
+use std::collections::hash_map::HashMap;
+use crate::parser::*;
+
+/// Largest edit distance at which a variable is considered a misspelling of a label.
+pub const MAX_TYPO_DISTANCE: usize = 2;
+
+/// Where a symbol was first referenced, and how many times it was referenced in total.
+pub struct SymRef {
+	pub count: u32,
+	pub line: String,
+	pub line_num: u32,
+	pub ins_ptr: u16,
+}
+
+impl SymRef {
+	pub fn new(line: &str, line_num: u32, ins_ptr: u16) -> Self {
+		SymRef{count: 0, line: line.to_string(), line_num, ins_ptr}
+	}
+}
+
+/// Levenshtein distance between two symbols.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+	let b = b.as_bytes();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.bytes().enumerate() {
+		let mut diag = row[0];
+		row[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let above = row[j + 1];
+			row[j + 1] = if ca == *cb { diag } else { 1 + diag.min(above).min(row[j]) };
+			diag = above;
+		}
+	}
+	row[b.len()]
+}
+
+/// Whether `sym` is near enough to `label` to be a misspelling of it; short symbols are
+/// within a couple of edits of almost anything, so at most half the characters may differ.
+fn is_probable_typo(distance: usize, sym: &str, label: &str) -> bool {
+	distance <= MAX_TYPO_DISTANCE && distance * 2 < sym.len().max(label.len())
+}
+
+/// Find variables which are referenced only once and are within [`MAX_TYPO_DISTANCE`] edits
+/// of a label; such variables are almost always a misspelt jump target. Must be called before
+/// RAM addresses are distributed to variables. Returns (variable sym_id, suggested label)
+/// pairs ordered by sym_id.
+pub fn find_probable_typos<'a>(sym_key_table: &'a SymKeyTable, sym_val_table: &[(u16, SymUse)],
+	sym_refs: &HashMap<usize, SymRef>) -> Vec<(usize, &'a str)>
+{
+	let labels: Vec<&str> = sym_key_table.iter()
+		.filter(|(_, sym_id)| sym_val_table[**sym_id].1 == SymUse::LROM)
+		.map(|(sym, _)| sym.as_str())
+		.collect();
+
+	let mut typos = vec![];
+	for (sym, sym_id) in sym_key_table {
+		let is_var = sym_val_table[*sym_id] == (DEFAULT_RAM_ADDRESS, SymUse::ARAM);
+		if !is_var || sym_refs.get(sym_id).map_or(0, |r| r.count) != 1 {
+			continue;
+		}
+		let closest = labels.iter()
+			.map(|label| (edit_distance(sym, label), *label))
+			.filter(|(distance, label)| is_probable_typo(*distance, sym, label))
+			.min();
+		if let Some((_, label)) = closest {
+			typos.push((*sym_id, label));
+		}
+	}
+	typos.sort();
+	typos
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_edit_distance(){
+		assert_eq!(edit_distance("LOOP", "LOOP"), 0);
+		assert_eq!(edit_distance("LOPP", "LOOP"), 1);
+		assert_eq!(edit_distance("LOP", "LOOP"), 1);
+		assert_eq!(edit_distance("OOLP", "LOOP"), 2);
+		assert_eq!(edit_distance("", "END"), 3);
+		assert_eq!(edit_distance("kitten", "sitting"), 3);
+	}
+
+	#[test]
+	fn test_find_probable_typos(){
+		let mut sym_key_table = HashMap::new();
+		let mut sym_val_table = vec![];
+		let mut sym_refs = HashMap::new();
+		let program = [
+			"(LOOP)", "@LOPP", "0;JMP",         // typo of LOOP, used once
+			"@i", "M=1", "@i", "M=M+1",         // ordinary variable, used twice
+			"@j", "M=0", "(k)",                 // too short to be distinguishable
+			"@END", "@ENDD", "@ENDD", "(END)",  // typo of END, but used more than once
+			"@STOPX", "(STOP)",                 // closest label is suggested
+			"(STOPXYZ)",
+			"@R1",                              // predefined symbols are never typos
+		];
+		sym_key_table.insert("R1".to_string(), 0);
+		sym_val_table.push((1, SymUse::ARAM));
+		for (ins_ptr, line) in program.iter().enumerate() {
+			if let Ok(Some(Ins::A2{sym_id})) = parse_ins(line, ins_ptr as u16, &mut sym_key_table, &mut sym_val_table) {
+				sym_refs.entry(sym_id).or_insert_with(|| SymRef::new(line, ins_ptr as u32, ins_ptr as u16)).count += 1;
+			}
+		}
+
+		let typos = find_probable_typos(&sym_key_table, &sym_val_table, &sym_refs);
+		assert_eq!(typos, vec![(sym_key_table["LOPP"], "LOOP"), (sym_key_table["STOPX"], "STOP")]);
+		assert_eq!(sym_refs[&sym_key_table["LOPP"]].line, "@LOPP");
+	}
+}
@@ -0,0 +1,101 @@
+use std::io::{self, Write};
+use crate::assembler::{Program, Symbol};
+use crate::parser::SymUse;
+
+const BLANK_COLUMNS: &str = "                       ";
+
+/// Write a listing of an assembled program; each source line beside the ROM address and
+/// encoded word of its instructions, followed by the values of the symbols it uses. Lines with
+/// several instructions continue on following rows. The program must have been assembled with
+/// [`AssembleOptions::listing`](crate::AssembleOptions::listing) set and without errors.
+///
+/// ```text
+/// ROM    WORD               LINE | SOURCE
+/// 00000  0000000000010000      1 | @i    [i=16]
+/// ```
+pub fn write_listing<W: ?Sized>(program: &Program, out: &mut W) -> io::Result<()>
+	where W: Write
+{
+	writeln!(out, "ROM    WORD               LINE | SOURCE")?;
+	for line in &program.lines {
+		let start = line.rom_address as usize;
+		let words = &program.words[start..start + line.ins_count as usize];
+		match words.first() {
+			Some(word) => write!(out, "{:05}  {:016b}  {:>5} | {}", start, word, line.line_num, line.text)?,
+			None => write!(out, "{}  {:>5} | {}", BLANK_COLUMNS, line.line_num, line.text)?,
+		}
+		if !line.sym_ids.is_empty() {
+			let values: Vec<String> = line.sym_ids.iter()
+				.map(|sym_id| &program.symbols[*sym_id])
+				.map(|sym| format!("{}={}", sym.name, sym.address))
+				.collect();
+			write!(out, "    [{}]", values.join(", "))?;
+		}
+		writeln!(out)?;
+		for (i, word) in words.iter().enumerate().skip(1) {
+			writeln!(out, "{:05}  {:016b}        |", start + i, word)?;
+		}
+	}
+	Ok(())
+}
+
+/// Write every symbol of an assembled program, one per line as `NAME ADDRESS RAM|ROM`; RAM
+/// symbols first, each ordered by address then name. Includes the predefined symbols.
+pub fn write_symbols<W: ?Sized>(program: &Program, out: &mut W) -> io::Result<()>
+	where W: Write
+{
+	let mut symbols: Vec<&Symbol> = program.symbols.iter().collect();
+	symbols.sort_by_key(|sym| (sym.usage == SymUse::LROM, sym.address, &sym.name));
+	for sym in symbols {
+		let usage = match sym.usage {
+			SymUse::ARAM => "RAM",
+			SymUse::LROM => "ROM",
+		};
+		writeln!(out, "{} {} {}", sym.name, sym.address, usage)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::assembler::{assemble_program, AssembleOptions};
+
+	#[test]
+	fn test_write_listing(){
+		let asm = "// sum\n@i\nM=1\n(LOOP)\n@i; M=M+1\n@LOOP\n0;JMP\n";
+		let options = AssembleOptions{listing: true, ..AssembleOptions::default()};
+		let program = assemble_program(&mut asm.as_bytes(), &options).unwrap();
+		let mut out = vec![];
+		write_listing(&program, &mut out).unwrap();
+		assert_eq!(String::from_utf8(out).unwrap(), "\
+			ROM    WORD               LINE | SOURCE\n\
+			\x20                            1 | // sum\n\
+			00000  0000000000010000      2 | @i    [i=16]\n\
+			00001  1110111111001000      3 | M=1\n\
+			\x20                            4 | (LOOP)    [LOOP=2]\n\
+			00002  0000000000010000      5 | @i; M=M+1    [i=16]\n\
+			00003  1111110111001000        |\n\
+			00004  0000000000000010      6 | @LOOP    [LOOP=2]\n\
+			00005  1110101010000111      7 | 0;JMP\n\
+		");
+
+		// Lines should only be recorded when requested.
+		let program = assemble_program(&mut asm.as_bytes(), &AssembleOptions::default()).unwrap();
+		assert!(program.lines.is_empty());
+		assert_eq!(program.words.len(), 6);
+	}
+
+	#[test]
+	fn test_write_symbols(){
+		let asm = "@j\n(LOOP)\n@i\n@LOOP\n(END)\n@j\n";
+		let program = assemble_program(&mut asm.as_bytes(), &AssembleOptions::default()).unwrap();
+		let mut out = vec![];
+		write_symbols(&program, &mut out).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		let lines: Vec<&str> = out.lines().collect();
+		assert_eq!(lines.len(), 16 + 5 + 2 + 4);
+		assert_eq!(&lines[..6], ["R0 0 RAM", "SP 0 RAM", "LCL 1 RAM", "R1 1 RAM", "ARG 2 RAM", "R2 2 RAM"]);
+		assert_eq!(&lines[21..], ["j 16 RAM", "i 17 RAM", "SCREEN 16384 RAM", "KBD 24576 RAM", "LOOP 1 ROM", "END 3 ROM"]);
+	}
+}
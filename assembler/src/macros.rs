@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use crate::parser::{code_chars, split_ins, strip_comment};
+
+/// Deepest nesting of macro invocations within macro bodies.
+pub const MAX_EXPANSION_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum MacroError {
+	MacroMissingName,
+	InvalidName,
+	DuplicateMacro,
+	NestedMacro,
+	UnmatchedEndmacro,
+	Unterminated{line: String, line_num: u32},
+	WrongArgCount{expected: usize, found: usize},
+	TooDeep,
+}
+
+impl MacroError {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			MacroError::MacroMissingName => "Expected a name after '.macro'.",
+			MacroError::InvalidName => "Macro names and parameters must be symbols.",
+			MacroError::DuplicateMacro => "Duplicate macro definition!",
+			MacroError::NestedMacro => "Found '.macro' within a macro definition; macros cannot be defined by macros.",
+			MacroError::UnmatchedEndmacro => "Found '.endmacro' outside of a macro definition.",
+			MacroError::Unterminated{..} => "Expected '.endmacro' to close macro definition before the end of the file.",
+			MacroError::WrongArgCount{..} => "Wrong number of arguments for macro.",
+			MacroError::TooDeep => "Macro invocations nested too deeply; is a macro invoking itself?",
+		}
+	}
+}
+
+struct Macro {
+	params: Vec<String>,
+	body: Vec<String>,
+}
+
+struct Definition {
+	name: String,
+	line: String,
+	line_num: u32,
+	mac: Macro,
+}
+
+fn is_sym(word: &str) -> bool {
+	let is_sym_char = |c: char| c.is_ascii_alphanumeric() || "_.$:".contains(c);
+	word.chars().next().is_some_and(|c| !c.is_ascii_digit()) && word.chars().all(is_sym_char)
+}
+
+/// Replace every symbol of a line which is a parameter with its argument; comments and
+/// character literals are left as they are.
+fn substitute(line: &str, params: &[String], args: &[&str]) -> String {
+	let code = strip_comment(line);
+	let mut out = String::with_capacity(line.len());
+	let mut sym_start = None;
+	let end_sym = |out: &mut String, start: usize, end: usize| {
+		let sym = &code[start..end];
+		match params.iter().position(|param| param == sym) {
+			Some(i) => out.push_str(args[i]),
+			None => out.push_str(sym),
+		}
+	};
+	for (pos, c, literal) in code_chars(line) {
+		if !literal && (c.is_ascii_alphanumeric() || "_.$:".contains(c)) {
+			sym_start.get_or_insert(pos);
+			continue;
+		}
+		if let Some(start) = sym_start.take() {
+			end_sym(&mut out, start, pos);
+		}
+		out.push(c);
+	}
+	if let Some(start) = sym_start {
+		end_sym(&mut out, start, code.len());
+	}
+	out.push_str(&line[code.len()..]);
+	out
+}
+
+/// Macros; lines between `.macro NAME PARAM...` and `.endmacro` define a macro, which is
+/// expanded wherever an instruction starts with its name, substituting each parameter symbol
+/// of its body with the argument given. Arguments are separated by commas or whitespace; an
+/// invocation ends at a `;`, like any instruction, so `LOAD 5, R13; D=M` invokes LOAD then
+/// assembles `D=M`. Macros
+/// may invoke other macros, but not define them. A label within a macro is declared by every
+/// expansion, so jumps within a macro should use relative addresses instead.
+///
+/// ```text
+/// .macro LOAD value, dest
+/// @value
+/// D=A
+/// @dest
+/// M=D
+/// .endmacro
+///
+/// LOAD 5, R13
+/// ```
+#[derive(Default)]
+pub struct Macros {
+	macros: HashMap<String, Macro>,
+	definition: Option<Definition>,
+}
+
+impl Macros {
+	pub fn new() -> Self {
+		Macros::default()
+	}
+
+	/// Process a line which is to be assembled; returns the lines of its expansion, if it
+	/// invokes a macro, otherwise None if the line should be assembled as it is. Macro
+	/// directives, and lines of macro definitions, expand to no lines.
+	pub fn expand(&mut self, line: &str, line_num: u32) -> Result<Option<Vec<String>>, MacroError> {
+		let mut words = strip_comment(line).split([',', ' ', '\t']).filter(|w| !w.is_empty());
+		let first = words.next();
+		if let Some(definition) = &mut self.definition {
+			match first {
+				Some(".macro") => return Err(MacroError::NestedMacro),
+				Some(".endmacro") => {
+					let definition = self.definition.take().unwrap();
+					self.macros.insert(definition.name, definition.mac);
+				},
+				_ => definition.mac.body.push(line.to_string()),
+			}
+			return Ok(Some(vec![]));
+		}
+		match first {
+			Some(".macro") => {
+				let name = words.next().ok_or(MacroError::MacroMissingName)?;
+				let params: Vec<String> = words.map(str::to_string).collect();
+				if !is_sym(name) || !params.iter().all(|param| is_sym(param)) {
+					return Err(MacroError::InvalidName);
+				}
+				if self.macros.contains_key(name) {
+					return Err(MacroError::DuplicateMacro);
+				}
+				let mac = Macro{params, body: vec![]};
+				self.definition = Some(Definition{name: name.to_string(), line: line.to_string(), line_num, mac});
+				Ok(Some(vec![]))
+			},
+			Some(".endmacro") => Err(MacroError::UnmatchedEndmacro),
+			_ if split_ins(line).iter().any(|(_, ins)| self.is_invocation(ins)) => {
+				let mut lines = vec![];
+				self.expand_into(line, 0, &mut lines)?;
+				Ok(Some(lines))
+			},
+			_ => Ok(None),
+		}
+	}
+
+	fn is_invocation(&self, ins: &str) -> bool {
+		strip_comment(ins).split([',', ' ', '\t']).find(|w| !w.is_empty()).is_some_and(|name| self.macros.contains_key(name))
+	}
+
+	/// Expand a line into `lines`; each instruction of a line which invokes a macro is placed
+	/// on its own line.
+	fn expand_into(&self, line: &str, depth: usize, lines: &mut Vec<String>) -> Result<(), MacroError> {
+		let inss = split_ins(line);
+		if inss.len() > 1 && inss.iter().any(|(_, ins)| self.is_invocation(ins)) {
+			for (_, ins) in inss {
+				self.expand_into(ins.trim(), depth, lines)?;
+			}
+			return Ok(());
+		}
+		let mut words = strip_comment(line).split([',', ' ', '\t']).filter(|w| !w.is_empty());
+		let mac = match words.next().and_then(|name| self.macros.get(name)) {
+			Some(mac) => mac,
+			None => {
+				lines.push(line.to_string());
+				return Ok(());
+			},
+		};
+		if depth == MAX_EXPANSION_DEPTH {
+			return Err(MacroError::TooDeep);
+		}
+		let args: Vec<&str> = words.collect();
+		if args.len() != mac.params.len() {
+			return Err(MacroError::WrongArgCount{expected: mac.params.len(), found: args.len()});
+		}
+		for body_line in &mac.body {
+			self.expand_into(&substitute(body_line, &mac.params, &args), depth + 1, lines)?;
+		}
+		Ok(())
+	}
+
+	/// Check every definition was closed, at the end of the input.
+	pub fn finish(&mut self) -> Result<(), MacroError> {
+		match self.definition.take() {
+			Some(definition) => Err(MacroError::Unterminated{line: definition.line, line_num: definition.line_num}),
+			None => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn expanded(text: &str) -> Result<Vec<String>, MacroError> {
+		let mut macros = Macros::new();
+		let mut lines = vec![];
+		for (i, line) in text.lines().enumerate() {
+			match macros.expand(line, i as u32 + 1)? {
+				Some(expansion) => lines.extend(expansion),
+				None => lines.push(line.to_string()),
+			}
+		}
+		macros.finish()?;
+		Ok(lines)
+	}
+
+	#[test]
+	fn test_macros(){
+		// Invocations should be replaced by the body of their macro, with arguments substituted.
+		let text = ".macro LOAD value, dest\n@value // load value\nD=A\n@dest\nM=D\n.endmacro\n@1\nLOAD 5, R13\nLOAD\tSCREEN valued";
+		assert_eq!(expanded(text).unwrap(), vec!["@1", "@5 // load value", "D=A", "@R13", "M=D",
+			"@SCREEN // load value", "D=A", "@valued", "M=D"]);
+
+		// Only whole symbols outside character literals should be substituted.
+		let text = ".macro PUT c\n@c.x; @'c'; @$+1\n.endmacro\nPUT 2";
+		assert_eq!(expanded(text).unwrap(), vec!["@c.x; @'c'; @$+1"]);
+
+		// Macros should expand within macros.
+		let text = ".macro PUSH_D\n@SP\nAM=M+1\nA=A-1\nM=D\n.endmacro\n.macro PUSH x\n@x\nD=A\nPUSH_D\n.endmacro\nPUSH 7";
+		assert_eq!(expanded(text).unwrap(), vec!["@7", "D=A", "@SP", "AM=M+1", "A=A-1", "M=D"]);
+
+		// Invocations should be found in any instruction of a line, in source and in bodies.
+		let text = ".macro INC_D\nD=D+1\n.endmacro\n.macro TWICE\nINC_D; INC_D\n.endmacro\n@1; INC_D; D;JGT // c\nTWICE";
+		assert_eq!(expanded(text).unwrap(), vec!["@1", "D=D+1", "D;JGT // c", "D=D+1", "D=D+1"]);
+		let text = ".macro LOAD value, dest\n@value\nD=A\n@dest\nM=D\n.endmacro\nLOAD 5, R13; D=M";
+		assert_eq!(expanded(text).unwrap(), vec!["@5", "D=A", "@R13", "M=D", "D=M"]);
+		assert_eq!(expanded(".macro X\n.endmacro\n@1; D=A // X"), Ok(vec!["@1; D=A // X".to_string()]));
+
+		// Malformed definitions and invocations should be detected.
+		assert_eq!(expanded(".macro"), Err(MacroError::MacroMissingName));
+		assert_eq!(expanded(".macro 1X"), Err(MacroError::InvalidName));
+		assert_eq!(expanded(".macro X a-b"), Err(MacroError::InvalidName));
+		assert_eq!(expanded(".macro X\n.endmacro\n.macro X"), Err(MacroError::DuplicateMacro));
+		assert_eq!(expanded(".macro X\n.macro Y"), Err(MacroError::NestedMacro));
+		assert_eq!(expanded(".endmacro"), Err(MacroError::UnmatchedEndmacro));
+		assert_eq!(expanded("@1\n.macro X\n@1"), Err(MacroError::Unterminated{line: ".macro X".to_string(), line_num: 2}));
+		assert_eq!(expanded(".macro X a\n.endmacro\nX"), Err(MacroError::WrongArgCount{expected: 1, found: 0}));
+		assert_eq!(expanded(".macro X\nX\n.endmacro\nX"), Err(MacroError::TooDeep));
+	}
+}
@@ -1,26 +1,161 @@
-use std::io::{BufReader, BufWriter};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use std::fs::File;
 use clap::Parser;
 use crate::assembler::*;
-
-mod parser;
-mod encoder;
-mod assembler;
+use n2t_assembler::{assembler, explain, generator, listing, object};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = "Translate a Hack assembly (.asm) file to a Hack binary (.hack) file.")]
 struct Args {
-		#[arg(name = "asm", help = "path to input assembly .asm file")]
-		asm_file_path: String,
+		#[arg(name = "asm", required_unless_present_any = ["explain", "gen_asm"],
+			help = "path to input assembly .asm file, or paths to object files with --link")]
+		input_paths: Vec<PathBuf>,
 		#[arg(name = "out", short, long, help = "path to output binary .hack file", default_value = "out.hack")]
-		bin_file_path: String,
+		bin_file_path: PathBuf,
+		#[arg(name = "format", short, long, value_enum, default_values_t = [OutputFormat::Text],
+			help = "output format/s; formats other than text are written beside the output file with their own extension. \
+				For a map of symbol addresses use --symbols, or --map when linking")]
+		formats: Vec<OutputFormat>,
+		#[arg(long, help = "assemble an object to link with others, written to the output path, instead of a binary")]
+		object: bool,
+		#[arg(long, conflicts_with = "object", help = "link object files, in the order given, into a binary")]
+		link: bool,
+		#[arg(long, value_name = "PATH", requires = "link", help = "also write a map of the address of each linked object, global and variable")]
+		map: Option<PathBuf>,
+		#[arg(long, value_name = "PATH", help = "also write a listing of each source line with its ROM address, binary word and symbol values")]
+		listing: Option<PathBuf>,
+		#[arg(long, value_name = "PATH", help = "also write the final address of every symbol, and whether it is in RAM or ROM")]
+		symbols: Option<PathBuf>,
+		#[arg(long, value_name = "CODE", help = "print an extended description of an error or warning code, e.g. E006")]
+		explain: Option<String>,
+		#[arg(long, value_name = "N", default_value_t = 10, help = "stop after N errors; 0 reports every error")]
+		max_errors: u32,
+		#[arg(short = 'W', value_enum, value_name = "LEVEL", default_value_t = WarningLevel::Warn,
+			help = "warning level; error fails assembly on any warning")]
+		warnings: WarningLevel,
+		#[arg(long, help = "same as -W error")]
+		treat_warnings_as_errors: bool,
+		#[arg(short = 'D', value_name = "SYMBOL", help = "define a symbol for conditional assembly; '.if SYMBOL'")]
+		defines: Vec<String>,
+		#[arg(long, value_name = "INS_COUNT", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(0..=generator::MAX_INS_COUNT as u64),
+			help = "print a random valid program of INS_COUNT instructions, at most 32768, for testing and benchmarking")]
+		gen_asm: Option<usize>,
+		#[arg(long, default_value_t = 1, requires = "gen_asm", help = "seed of the generated program")]
+		seed: u64,
+		#[arg(long, default_value_t = 0.05, requires = "gen_asm", help = "probability of a label before each generated instruction")]
+		label_density: f64,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, clap::ValueEnum)]
+enum WarningLevel {
+	Warn,
+	Error,
+}
+
+fn output_path(bin_file_path: &Path, format: OutputFormat) -> PathBuf {
+	match format {
+		OutputFormat::Text => bin_file_path.to_path_buf(),
+		_ => bin_file_path.with_extension(format.extension()),
+	}
+}
+
+type Report<T> = fn(&T, &mut BufWriter<File>) -> io::Result<()>;
+
+fn write_report<T>(path: &Path, value: &T, write: Report<T>) -> io::Result<()> {
+	let mut writer = BufWriter::new(File::create(path)?);
+	write(value, &mut writer)?;
+	writer.flush()
+}
+
+fn create_bin_files(bin_file_path: &Path, formats: &[OutputFormat]) -> Vec<(OutputFormat, BufWriter<File>)> {
+	let mut bin_files = vec![];
+	for format in formats {
+		let path = output_path(bin_file_path, *format);
+		match File::create(&path) {
+			Ok(file) => bin_files.push((*format, BufWriter::new(file))),
+			Err(e) => {
+				println!("error: failed to create output file '{}': {}", path.display(), e);
+				std::process::exit(-1);
+			}
+		}
+	}
+	bin_files
+}
+
+fn write_bin_files(bin_files: &mut [(OutputFormat, BufWriter<File>)], words: &[u16]) {
+	for (format, bin_writer) in bin_files {
+		if let Err(e) = write_words(words, *format, bin_writer) {
+			println!("error: {}", e);
+			std::process::exit(-1);
+		}
+	}
 }
 
 fn main(){
-	let args = Args::parse();
+	let mut args = Args::parse();
+	args.formats.sort();
+	args.formats.dedup();
 
-	let asm_file = match File::open(args.asm_file_path) {
+	if let Some(code) = args.explain {
+		match explain::explain(&code) {
+			Some(text) => println!("{}", text),
+			None => {
+				println!("error: '{}' is not a known error code", code);
+				std::process::exit(-1);
+			}
+		}
+		return;
+	}
+
+	if let Some(ins_count) = args.gen_asm {
+		let options = generator::GenOptions{ins_count, seed: args.seed, label_density: args.label_density,
+			..generator::GenOptions::default()};
+		print!("{}", generator::generate_asm(&options));
+		return;
+	}
+
+	if args.link {
+		let mut bin_files = create_bin_files(&args.bin_file_path, &args.formats);
+		let mut units = vec![];
+		for path in &args.input_paths {
+			match File::open(path).and_then(|file| object::read_object(&mut BufReader::new(file))) {
+				Ok(object) => units.push((path.display().to_string(), object)),
+				Err(e) => {
+					println!("error: failed to read object '{}': {}", path.display(), e);
+					std::process::exit(-1);
+				}
+			}
+		}
+		match object::link(&units) {
+			Ok(linked) => {
+				write_bin_files(&mut bin_files, &linked.words);
+				if let Some(path) = &args.map {
+					if let Err(e) = write_report(path, &linked, object::write_map) {
+						println!("error: failed to write map '{}': {}", path.display(), e);
+						std::process::exit(-1);
+					}
+				}
+				println!("Linked {} instructions from {} objects", linked.words.len(), units.len());
+			},
+			Err(errors) => {
+				for e in &errors {
+					println!("error: {}", e);
+				}
+				println!("Linking failed with {} errors", errors.len());
+				std::process::exit(-1);
+			}
+		}
+		return;
+	}
+
+	if args.input_paths.len() > 1 {
+		println!("error: expected a single input .asm file; did you mean to --link objects?");
+		std::process::exit(-1);
+	}
+
+	let asm_file = match File::open(&args.input_paths[0]) {
 		Ok(file) => file,
 		Err(e) => {
 			println!("error: failed to open input .asm file: {}", e);
@@ -28,24 +163,47 @@ fn main(){
 		}
 	};
 
-	let bin_file = match File::create(args.bin_file_path) {
-		Ok(file) => file,
-		Err(e) => {
-			println!("error: failed to create output .hack file: {}", e);
-			std::process::exit(-1);
-		}
+	let mut bin_files = match args.object {
+		true => vec![],
+		false => create_bin_files(&args.bin_file_path, &args.formats),
 	};
 
 	let mut asm_reader = BufReader::new(asm_file);
-	let mut bin_writer = BufWriter::new(bin_file);
+
+	let options = AssembleOptions{
+		max_errors: if args.max_errors == 0 { None } else { Some(args.max_errors) },
+		warnings_as_errors: args.treat_warnings_as_errors || args.warnings == WarningLevel::Error,
+		defines: args.defines.into_iter().collect(),
+		listing: args.listing.is_some(),
+		object: args.object,
+	};
 
 	let now = Instant::now();
-	let result = assemble(&mut asm_reader, &mut bin_writer);
+	let result = assemble_program(&mut asm_reader, &options);
 	let elapsed = now.elapsed();
 
 	match result {
-		Ok((line_count, ins_count)) => {
-			println!("Translated {} instructions ({} lines) in {:.2?}", ins_count, line_count, elapsed);
+		Ok(program) if program.error_count > 0 => {
+			println!("Assembly failed with {} errors", program.error_count);
+			std::process::exit(-1);
+		},
+		Ok(program) => {
+			write_bin_files(&mut bin_files, &program.words);
+			let object_path = args.object.then_some(args.bin_file_path);
+			let reports: [(&Option<PathBuf>, &str, Report<Program>); 3] = [
+				(&object_path, "object", object::write_object),
+				(&args.listing, "listing", listing::write_listing),
+				(&args.symbols, "symbols", listing::write_symbols),
+			];
+			for (path, name, write) in reports {
+				if let Some(path) = path {
+					if let Err(e) = write_report(path, &program, write) {
+						println!("error: failed to write {} '{}': {}", name, path.display(), e);
+						std::process::exit(-1);
+					}
+				}
+			}
+			println!("Translated {} instructions ({} lines) in {:.2?}", program.ins_count, program.line_count, elapsed);
 		},
 		Err(e) => {
 			println!("error: {}", e);
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use crate::assembler::{Program, Symbol};
+use crate::parser::SymUse;
+
+const FIRST_VAR_RAM_ADDRESS: u16 = 16;
+const SCR_RAM_ADDRESS: u16 = 16384;
+const ROM_SIZE: usize = 32768;
+const MAX_ROM_ADDRESS: u16 = ROM_SIZE as u16 - 1;
+
+/// How the linker relocates an instruction of an object.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Reloc {
+	/// Used as assembled.
+	Abs,
+	/// A ROM address within the object; offset by the address the object is linked at.
+	Rom,
+	/// A variable private to the object; allocated RAM by the linker.
+	Var(String),
+	/// A global of any object; an extern, or a global variable.
+	Global(String),
+}
+
+/// A separately assembled unit of a program, to be linked with others; see [`link`].
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Object {
+	pub words: Vec<u16>,
+	/// The relocation of each word.
+	pub relocs: Vec<Reloc>,
+	/// Symbols exported by the object; labels have their ROM address within the object,
+	/// variables are allocated RAM by the linker.
+	pub globals: Vec<Symbol>,
+}
+
+/// Write a program assembled with [`AssembleOptions::object`](crate::AssembleOptions::object)
+/// as an object; a text file of a line per global, as `global NAME rom OFFSET` or
+/// `global NAME ram`, followed by a line per word with its relocation, if any, as one of
+/// `WORD rom`, `WORD var NAME` or `WORD global NAME`.
+pub fn write_object<W: ?Sized>(program: &Program, out: &mut W) -> io::Result<()>
+	where W: Write
+{
+	writeln!(out, "// n2tasm object")?;
+	for sym in &program.globals {
+		match sym.usage {
+			SymUse::LROM => writeln!(out, "global {} rom {}", sym.name, sym.address)?,
+			SymUse::ARAM => writeln!(out, "global {} ram", sym.name)?,
+		}
+	}
+	for (word, reloc) in program.words.iter().zip(&program.relocs) {
+		match reloc {
+			Reloc::Abs => writeln!(out, "{:016b}", word)?,
+			Reloc::Rom => writeln!(out, "{:016b} rom", word)?,
+			Reloc::Var(sym) => writeln!(out, "{:016b} var {}", word, sym)?,
+			Reloc::Global(sym) => writeln!(out, "{:016b} global {}", word, sym)?,
+		}
+	}
+	Ok(())
+}
+
+/// Read an object written by [`write_object`]. ROM addresses, of labels and `rom` words, must
+/// lie within the object.
+pub fn read_object<R: ?Sized>(obj_in: &mut R) -> io::Result<Object>
+	where R: BufRead
+{
+	let mut object = Object::default();
+	for (i, line) in obj_in.lines().enumerate() {
+		let line = line?;
+		let malformed = || io::Error::new(io::ErrorKind::InvalidData, format!("malformed object line {}: '{}'", i + 1, line));
+		if line.trim().is_empty() || line.starts_with("//") {
+			continue;
+		}
+		let words: Vec<&str> = line.split_whitespace().collect();
+		match words[..] {
+			["global", name, "rom", offset] => {
+				let address = offset.parse().map_err(|_| malformed())?;
+				object.globals.push(Symbol{name: name.to_string(), address, usage: SymUse::LROM});
+			},
+			["global", name, "ram"] => {
+				object.globals.push(Symbol{name: name.to_string(), address: 0, usage: SymUse::ARAM});
+			},
+			[word, ref reloc @ ..] if word.len() == 16 => {
+				let word = u16::from_str_radix(word, 2).map_err(|_| malformed())?;
+				let reloc = match reloc {
+					[] => Reloc::Abs,
+					["rom"] => Reloc::Rom,
+					["var", sym] => Reloc::Var(sym.to_string()),
+					["global", sym] => Reloc::Global(sym.to_string()),
+					_ => return Err(malformed()),
+				};
+				object.words.push(word);
+				object.relocs.push(reloc);
+			},
+			_ => return Err(malformed()),
+		}
+	}
+
+	let max_address = object.words.len().min(MAX_ROM_ADDRESS as usize) as u16;
+	let outside = |what: String| io::Error::new(io::ErrorKind::InvalidData,
+		format!("{} is outside the object of {} words", what, object.words.len()));
+	if let Some(sym) = object.globals.iter().find(|sym| sym.usage == SymUse::LROM && sym.address > max_address) {
+		return Err(outside(format!("global '{}' at ROM offset {}", sym.name, sym.address)));
+	}
+	let mut rom_words = object.words.iter().zip(&object.relocs).filter(|(_, reloc)| **reloc == Reloc::Rom);
+	if let Some((word, _)) = rom_words.find(|(word, _)| **word > max_address) {
+		return Err(outside(format!("ROM offset {}", word)));
+	}
+	Ok(object)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+	DuplicateGlobal{sym: String, first: String, second: String},
+	UndefinedGlobal{sym: String, unit: String},
+	RomExhausted,
+	RamExhausted,
+	AddressOutOfRange{unit: String},
+}
+
+impl fmt::Display for LinkError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LinkError::DuplicateGlobal{sym, first, second} => write!(f, "global '{}' is defined by both '{}' and '{}'", sym, first, second),
+			LinkError::UndefinedGlobal{sym, unit} => write!(f, "'{}' references undefined global '{}'", unit, sym),
+			LinkError::RomExhausted => write!(f, "ROM exhausted; the objects do not fit in 32K words"),
+			LinkError::RamExhausted => write!(f, "RAM exhausted; the variables do not fit below the screen"),
+			LinkError::AddressOutOfRange{unit} => write!(f, "'{}' addresses ROM beyond 32K words", unit),
+		}
+	}
+}
+
+/// Where an object was linked.
+#[derive(Debug, PartialEq)]
+pub struct UnitLayout {
+	pub name: String,
+	pub rom_address: u16,
+	pub rom_size: u16,
+	/// The private variables of the object, in order of allocation.
+	pub vars: Vec<Symbol>,
+}
+
+/// A linked program.
+#[derive(Debug, PartialEq)]
+pub struct Linked {
+	pub words: Vec<u16>,
+	pub units: Vec<UnitLayout>,
+	/// Every global at its final address, with the index of its defining unit.
+	pub globals: Vec<(Symbol, usize)>,
+}
+
+/// Link objects, named by their units, into a program. Objects are placed in ROM in the
+/// order given, so the first should hold the entry point. Variables are allocated RAM in
+/// order of first reference; global variables first. Reports every error; each duplicate and
+/// undefined global, each unit which addresses beyond ROM and any exhausted memory.
+pub fn link(units: &[(String, Object)]) -> Result<Linked, Vec<LinkError>> {
+	let mut layouts = vec![];
+	let mut rom_size = 0;
+	for (name, object) in units {
+		layouts.push(UnitLayout{name: name.clone(), rom_address: rom_size as u16, rom_size: object.words.len() as u16, vars: vec![]});
+		rom_size += object.words.len();
+		if rom_size > ROM_SIZE {
+			return Err(vec![LinkError::RomExhausted]);
+		}
+	}
+
+	let mut next_var_ram_address = FIRST_VAR_RAM_ADDRESS;
+	let mut alloc = || {
+		next_var_ram_address += 1;
+		match next_var_ram_address > SCR_RAM_ADDRESS {
+			true => Err(LinkError::RamExhausted),
+			false => Ok(next_var_ram_address - 1),
+		}
+	};
+
+	let relocate = |unit: &String, address: u16, offset: u16| match address.checked_add(offset) {
+		Some(address) if address <= MAX_ROM_ADDRESS => Ok(address),
+		_ => Err(LinkError::AddressOutOfRange{unit: unit.clone()}),
+	};
+	let report = |errors: &mut Vec<LinkError>, e: LinkError| {
+		if !errors.contains(&e) {
+			errors.push(e);
+		}
+		0
+	};
+
+	let mut errors = vec![];
+	let mut globals: Vec<(Symbol, usize)> = vec![];
+	let mut global_ids: HashMap<&str, usize> = HashMap::new();
+	for (i, (unit, object)) in units.iter().enumerate() {
+		for sym in &object.globals {
+			if let Some(&id) = global_ids.get(sym.name.as_str()) {
+				let first = units[globals[id].1].0.clone();
+				errors.push(LinkError::DuplicateGlobal{sym: sym.name.clone(), first, second: unit.clone()});
+				continue;
+			}
+			let address = match sym.usage {
+				SymUse::LROM => relocate(unit, layouts[i].rom_address, sym.address),
+				SymUse::ARAM => alloc(),
+			};
+			let address = match address {
+				Ok(address) => address,
+				Err(e) => report(&mut errors, e),
+			};
+			global_ids.insert(sym.name.as_str(), globals.len());
+			globals.push((Symbol{name: sym.name.clone(), address, usage: sym.usage}, i));
+		}
+	}
+
+	let mut words = Vec::with_capacity(rom_size);
+	for ((unit, object), layout) in units.iter().zip(&mut layouts) {
+		let mut vars = HashMap::new();
+		let mut undefined = vec![];
+		for (word, reloc) in object.words.iter().zip(&object.relocs) {
+			words.push(match reloc {
+				Reloc::Abs => *word,
+				Reloc::Rom => relocate(unit, layout.rom_address, *word).unwrap_or_else(|e| report(&mut errors, e)),
+				Reloc::Var(sym) => match vars.get(sym) {
+					Some(address) => *address,
+					None => {
+						let address = match alloc() {
+							Ok(address) => address,
+							Err(e) => report(&mut errors, e),
+						};
+						vars.insert(sym, address);
+						layout.vars.push(Symbol{name: sym.clone(), address, usage: SymUse::ARAM});
+						address
+					},
+				},
+				Reloc::Global(sym) => match global_ids.get(sym.as_str()) {
+					Some(&id) => globals[id].0.address,
+					None => {
+						if !undefined.contains(&sym) {
+							undefined.push(sym);
+							errors.push(LinkError::UndefinedGlobal{sym: sym.clone(), unit: unit.clone()});
+						}
+						0
+					},
+				},
+			});
+		}
+	}
+	match errors.is_empty() {
+		true => Ok(Linked{words, units: layouts, globals}),
+		false => Err(errors),
+	}
+}
+
+/// Write a map of a linked program; the ROM address and size of each unit, then the final
+/// address of every global and of the private variables of each unit.
+///
+/// ```text
+/// ROM    SIZE   VARS  UNIT
+/// 00000  120    2     main.obj
+///
+/// ADDR   USE  UNIT      SYMBOL
+/// 00016  RAM  main.obj  count
+/// ```
+pub fn write_map<W: ?Sized>(linked: &Linked, out: &mut W) -> io::Result<()>
+	where W: Write
+{
+	writeln!(out, "ROM    SIZE   VARS  UNIT")?;
+	for unit in &linked.units {
+		writeln!(out, "{:05}  {:<5}  {:<4}  {}", unit.rom_address, unit.rom_size, unit.vars.len(), unit.name)?;
+	}
+
+	let mut syms: Vec<(&Symbol, &str, bool)> = linked.globals.iter()
+		.map(|(sym, unit)| (sym, linked.units[*unit].name.as_str(), true))
+		.collect();
+	for unit in &linked.units {
+		syms.extend(unit.vars.iter().map(|sym| (sym, unit.name.as_str(), false)));
+	}
+	syms.sort_by_key(|(sym, _, _)| (sym.usage == SymUse::ARAM, sym.address));
+	let unit_width = linked.units.iter().map(|unit| unit.name.len()).max().unwrap_or(0).max(4);
+
+	writeln!(out)?;
+	writeln!(out, "ADDR   USE  {:<width$}  SYMBOL", "UNIT", width = unit_width)?;
+	for (sym, unit, global) in syms {
+		let usage = match sym.usage {
+			SymUse::ARAM => "RAM",
+			SymUse::LROM => "ROM",
+		};
+		let scope = if global { "" } else { " (private)" };
+		writeln!(out, "{:05}  {}  {:<width$}  {}{}", sym.address, usage, unit, sym.name, scope, width = unit_width)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::assembler::{assemble_program, AssembleOptions};
+
+	fn assemble_object(asm: &str) -> Object {
+		let options = AssembleOptions{object: true, ..AssembleOptions::default()};
+		let program = assemble_program(&mut asm.as_bytes(), &options).unwrap();
+		assert_eq!(program.error_count, 0);
+		let mut out = vec![];
+		write_object(&program, &mut out).unwrap();
+		read_object(&mut out.as_slice()).unwrap()
+	}
+
+	#[test]
+	fn test_object(){
+		let object = assemble_object(".global main\n.global count\n.global total\n.extern inc\n(main)\n@i\n@count\n@inc\n@main\n@$-1\n@ $ - 1\n@SP\n");
+		assert_eq!(object.globals, vec![
+			Symbol{name: "main".to_string(), address: 0, usage: SymUse::LROM},
+			Symbol{name: "count".to_string(), address: 0, usage: SymUse::ARAM},
+			Symbol{name: "total".to_string(), address: 0, usage: SymUse::ARAM},
+		]);
+		assert_eq!(object.relocs, vec![Reloc::Var("i".to_string()), Reloc::Global("count".to_string()),
+			Reloc::Global("inc".to_string()), Reloc::Rom, Reloc::Rom, Reloc::Rom, Reloc::Abs]);
+		assert_eq!(object.words[3..], [0, 3, 4, 0]);
+
+		let malformed = |text: &str| read_object(&mut text.as_bytes()).unwrap_err().kind();
+		assert_eq!(malformed("global main rom\n"), io::ErrorKind::InvalidData);
+		assert_eq!(malformed("0000000000000000 lib\n"), io::ErrorKind::InvalidData);
+		assert_eq!(malformed("000000000000000x\n"), io::ErrorKind::InvalidData);
+
+		// ROM offsets outside the object should be detected.
+		assert_eq!(malformed("global f rom 65535\n0000000000000000\n"), io::ErrorKind::InvalidData);
+		assert_eq!(malformed("global f rom 2\n0000000000000000\n"), io::ErrorKind::InvalidData);
+		assert_eq!(malformed("0000000000000011 rom\n0000000000000000\n"), io::ErrorKind::InvalidData);
+		assert!(read_object(&mut "global f rom 1\n0000000000000001 rom\n".as_bytes()).is_ok());
+	}
+
+	#[test]
+	fn test_link(){
+		let main = assemble_object(".global count\n.extern inc\n(LOOP)\n@i\n@count\n@inc\n@LOOP\n");
+		let inc = assemble_object(".global inc\n.extern count\n@x\n(inc)\n@count\n@inc\n");
+		let units = vec![("main".to_string(), main), ("inc".to_string(), inc)];
+
+		// Code should be relocated, globals shared and variables private to their object.
+		let linked = link(&units).unwrap();
+		assert_eq!(linked.words, vec![17, 16, 5, 0, 18, 16, 5]);
+		assert_eq!(linked.globals, vec![
+			(Symbol{name: "count".to_string(), address: 16, usage: SymUse::ARAM}, 0),
+			(Symbol{name: "inc".to_string(), address: 5, usage: SymUse::LROM}, 1),
+		]);
+		assert_eq!((linked.units[1].rom_address, linked.units[1].rom_size), (4, 3));
+		assert_eq!(linked.units[1].vars, vec![Symbol{name: "x".to_string(), address: 18, usage: SymUse::ARAM}]);
+
+		// Every duplicate and undefined global should be reported, naming the units involved.
+		let (main, inc) = (&units[0].1, &units[1].1);
+		let dup = Object{words: vec![], relocs: vec![], globals: inc.globals.clone()};
+		let units = [("inc".to_string(), inc.clone()), ("dup".to_string(), dup), ("main".to_string(), main.clone())];
+		assert_eq!(link(&units[..2]), Err(vec![
+			LinkError::DuplicateGlobal{sym: "inc".to_string(), first: "inc".to_string(), second: "dup".to_string()},
+			LinkError::UndefinedGlobal{sym: "count".to_string(), unit: "inc".to_string()},
+		]));
+		assert_eq!(link(&units[2..]), Err(vec![LinkError::UndefinedGlobal{sym: "inc".to_string(), unit: "main".to_string()}]));
+
+		let big = Object{words: vec![0; 20000], relocs: vec![Reloc::Abs; 20000], globals: vec![]};
+		assert_eq!(link(&[("a".to_string(), big.clone()), ("b".to_string(), big)]), Err(vec![LinkError::RomExhausted]));
+		let vars = (0..16400).map(|i| Reloc::Var(i.to_string())).collect();
+		let many = Object{words: vec![0; 16400], relocs: vars, globals: vec![]};
+		assert_eq!(link(&[("many".to_string(), many)]), Err(vec![LinkError::RamExhausted]));
+
+		// Relocated addresses outside ROM should be detected, not overflow.
+		let far = Object{words: vec![0], relocs: vec![Reloc::Abs], globals: vec![
+			Symbol{name: "f".to_string(), address: 65535, usage: SymUse::LROM}]};
+		assert_eq!(link(&[("far".to_string(), far)]), Err(vec![LinkError::AddressOutOfRange{unit: "far".to_string()}]));
+		let far = Object{words: vec![65535], relocs: vec![Reloc::Rom], globals: vec![]};
+		assert_eq!(link(&[("far".to_string(), far)]), Err(vec![LinkError::AddressOutOfRange{unit: "far".to_string()}]));
+
+		// Errors of relocation should be reported along with every other error.
+		let far = Object{words: vec![65535], relocs: vec![Reloc::Rom], globals: inc.globals.clone()};
+		assert_eq!(link(&[("inc".to_string(), inc.clone()), ("far".to_string(), far)]), Err(vec![
+			LinkError::DuplicateGlobal{sym: "inc".to_string(), first: "inc".to_string(), second: "far".to_string()},
+			LinkError::UndefinedGlobal{sym: "count".to_string(), unit: "inc".to_string()},
+			LinkError::AddressOutOfRange{unit: "far".to_string()},
+		]));
+	}
+
+	#[test]
+	fn test_write_map(){
+		let main = assemble_object(".global count\n.extern inc\n(LOOP)\n@i\n@count\n@inc\n@LOOP\n");
+		let inc = assemble_object(".global inc\n.extern count\n@x\n(inc)\n@count\n@inc\n");
+		let linked = link(&[("main.obj".to_string(), main), ("inc.obj".to_string(), inc)]).unwrap();
+		let mut out = vec![];
+		write_map(&linked, &mut out).unwrap();
+		assert_eq!(String::from_utf8(out).unwrap(), "\
+			ROM    SIZE   VARS  UNIT\n\
+			00000  4      1     main.obj\n\
+			00004  3      1     inc.obj\n\
+			\n\
+			ADDR   USE  UNIT      SYMBOL\n\
+			00005  ROM  inc.obj   inc\n\
+			00016  RAM  main.obj  count\n\
+			00017  RAM  main.obj  i (private)\n\
+			00018  RAM  inc.obj   x (private)\n\
+		");
+	}
+}
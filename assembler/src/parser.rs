@@ -1,5 +1,5 @@
 use std::collections::hash_map::{HashMap, Entry};
-use std::borrow::Borrow;
+use std::ops::Range;
 use enum_iterator::Sequence;
 
 pub const MAX_SYM_LEN: usize = 255;
@@ -12,13 +12,20 @@ pub const DEFAULT_RAM_ADDRESS: u16 = u16::MAX;
 type SymBuf = [u8; MAX_SYM_LEN];
 pub type MneBuf = [u8; MNE_BUF_LEN];
 
+/// Maps each symbol to its id; an index into the [`SymValTable`].
+pub type SymKeyTable = HashMap<String, usize>;
+/// The address of each symbol by id, and whether it is a RAM or ROM address.
+pub type SymValTable = Vec<(u16, SymUse)>;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SymUse {
 	ARAM,
 	LROM,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MneType {
 	Dest,
 	Comp,
@@ -26,6 +33,7 @@ pub enum MneType {
 }
 
 #[derive(Debug, PartialEq, Sequence, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DestMne {
 	DestM,
 	DestD,
@@ -45,6 +53,7 @@ pub enum DestMne {
 }
 
 #[derive(Debug, PartialEq, Sequence, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompMne {
 	Comp0,
 	Comp1,
@@ -86,6 +95,7 @@ pub enum CompMne {
 }
 
 #[derive(Debug, PartialEq, Sequence, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum JumpMne {
 	JumpJgt,
 	JumpJeq,
@@ -276,9 +286,11 @@ impl JumpMne {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Ins {
 	A1{cint: u16},
 	A2{sym_id: usize},
+	A3{cint: u16},
 	L1{sym_id: usize},
 	C1{dest: DestMne, comp: CompMne},
 	C2{dest: DestMne, comp: CompMne, jump: JumpMne},
@@ -286,6 +298,7 @@ pub enum Ins {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParseError {
 	UnknownMne{mne_type: Option<MneType>, mne_buf: MneBuf},
 	ExpectedFirstSymChar{found: char, pos: usize},
@@ -300,10 +313,161 @@ pub enum ParseError {
 	IntOverflow,
 	NotASCII,
 	CInsNop,
+	RelOutOfRange,
+	HexOverflow,
+	BinOverflow,
+	CharMissingClose,
+}
+
+impl ParseError {
+	/// Stable error code; see `--explain`.
+	pub fn code(&self) -> &'static str {
+		match self {
+			ParseError::UnknownMne{..}           => "E001",
+			ParseError::ExpectedFirstSymChar{..} => "E002",
+			ParseError::ExpectedSymChar{..}      => "E003",
+			ParseError::ExpectedDigit{..}        => "E004",
+			ParseError::UnexpectedChar{..}       => "E005",
+			ParseError::DuplicateLabel           => "E006",
+			ParseError::AInsMissingArg           => "E007",
+			ParseError::LInsMissingSym           => "E008",
+			ParseError::LInsMissingClose         => "E009",
+			ParseError::SymOverflow              => "E010",
+			ParseError::IntOverflow              => "E011",
+			ParseError::NotASCII                 => "E012",
+			ParseError::CInsNop                  => "E013",
+			ParseError::RelOutOfRange            => "E017",
+			ParseError::HexOverflow              => "E018",
+			ParseError::BinOverflow              => "E019",
+			ParseError::CharMissingClose         => "E020",
+		}
+	}
+
+	/// Offset the reported position of the error; for instructions parsed from a slice of
+	/// their line.
+	pub fn shifted(self, offset: usize) -> ParseError {
+		match self {
+			ParseError::ExpectedFirstSymChar{found, pos} => ParseError::ExpectedFirstSymChar{found, pos: pos + offset},
+			ParseError::ExpectedSymChar{found, pos}      => ParseError::ExpectedSymChar{found, pos: pos + offset},
+			ParseError::ExpectedDigit{found, pos}        => ParseError::ExpectedDigit{found, pos: pos + offset},
+			ParseError::UnexpectedChar{found, pos}       => ParseError::UnexpectedChar{found, pos: pos + offset},
+			e => e,
+		}
+	}
 }
 
 pub type ParseResult = Result<Option<Ins>, ParseError>;
 
+/// A value with the byte range of the line it was parsed from.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spanned<T> {
+	pub value: T,
+	pub span: Range<usize>,
+}
+
+/// The characters of a line up to any comment, with their positions and whether each is the
+/// character of a character literal; e.g. the `;` of `@';'`, which is neither a separator nor
+/// the start of a comment.
+pub fn code_chars(line: &str) -> impl Iterator<Item = (usize, char, bool)> + '_ {
+	let mut quote = 0;
+	line.char_indices()
+		.map(move |(pos, c)| {
+			quote = match (quote, c) {
+				(0, '\'') => 1,
+				(1, _) => 2,
+				_ => 0,
+			};
+			(pos, c, quote == 2)
+		})
+		.take_while(|&(_, c, literal)| literal || (c != '#' && c != '/'))
+}
+
+/// A line less any comment.
+pub fn strip_comment(line: &str) -> &str {
+	&line[..code_chars(line).last().map_or(0, |(pos, c, _)| pos + c.len_utf8())]
+}
+
+/// Split a line into its `;` separated instructions, each paired with its position in the line.
+///
+/// A `;` followed by a jump mnemonic (all begin with 'J') separates the comp and jump terms of a
+/// C-instruction rather than two instructions, unless the instruction is an A or L-instruction
+/// or already has a jump term. Thus `D=A;JMP` is one instruction, while `@5;D=A`, `D=A;M=D` and
+/// `0;JMP;@1` are two. Comments end the line.
+pub fn split_ins(line: &str) -> Vec<(usize, &str)> {
+	let mut inss = vec![];
+	let mut start = 0;
+	let mut first = None;
+	let mut has_jump = false;
+	for (pos, c, literal) in code_chars(line) {
+		if c.is_whitespace() || literal {
+			continue;
+		}
+		if c != ';' {
+			first.get_or_insert(c);
+			continue;
+		}
+		let is_c_ins = first.is_some_and(|c| c != '@' && c != '(');
+		let next = line[pos + 1..].chars().find(|c| !c.is_whitespace());
+		if is_c_ins && !has_jump && next == Some('J') {
+			has_jump = true;
+			continue;
+		}
+		inss.push((start, &line[start..pos]));
+		start = pos + 1;
+		first = None;
+		has_jump = false;
+	}
+	inss.push((start, &line[start..]));
+	inss
+}
+
+/// Parse every instruction of a line; see [`split_ins`] and [`parse_ins`]. `ins_ptr` is the ROM
+/// address of the first instruction, and advances past every instruction, or error, as
+/// `parse_ins` would expect. Each instruction spans its text, less whitespace and comments;
+/// each error spans its offending character, where known, otherwise its instruction.
+///
+/// # Example
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use n2t_assembler::parser::*;
+/// let mut sym_key_table = HashMap::new();
+/// let mut sym_val_table = vec![];
+/// let inss = parse_line("@5; D=A // load", 0, &mut sym_key_table, &mut sym_val_table);
+/// assert_eq!(inss[0], Ok(Spanned{value: Ins::A1{cint: 5}, span: 0..2}));
+/// assert_eq!(inss[1], Ok(Spanned{value: Ins::C1{dest: DestMne::DestD, comp: CompMne::CompA}, span: 4..7}));
+/// ```
+pub fn parse_line(line: &str, mut ins_ptr: u16, sym_key_table: &mut SymKeyTable,
+	sym_val_table: &mut SymValTable) -> Vec<Result<Spanned<Ins>, Spanned<ParseError>>> {
+	let mut inss = vec![];
+	for (offset, ins_str) in split_ins(line) {
+		let text = strip_comment(ins_str);
+		let start = offset + text.len() - text.trim_start().len();
+		let span = start..offset + text.trim_end().len();
+		match parse_ins(ins_str, ins_ptr, sym_key_table, sym_val_table) {
+			Ok(Some(ins)) => {
+				if !matches!(ins, Ins::L1{..}) {
+					ins_ptr = ins_ptr.saturating_add(1);
+				}
+				inss.push(Ok(Spanned{value: ins, span}));
+			},
+			Ok(None) => (),
+			Err(e) => {
+				let e = e.shifted(offset);
+				let span = match e {
+					ParseError::ExpectedFirstSymChar{pos, ..} | ParseError::ExpectedSymChar{pos, ..} |
+					ParseError::ExpectedDigit{pos, ..} | ParseError::UnexpectedChar{pos, ..} => pos..pos + 1,
+					_ => span,
+				};
+				ins_ptr = ins_ptr.saturating_add(1);
+				inss.push(Err(Spanned{value: e, span}));
+			},
+		}
+	}
+	inss
+}
+
 /// Parse a line of Hack assembly into its equivalent data representation. Populates the
 /// symbol table as new symbols are encountered. `ins_ptr` (instruction pointer) is expected to 
 /// be the current ROM address of the instruction being parsed.
@@ -339,22 +503,48 @@ pub type ParseResult = Result<Option<Ins>, ParseError>;
 /// as ```@D=M;JMP``` have conflicting use of the A-register. Such instructions are discouraged
 /// but not invalid; `parse_ins` does not restrict their use.
 ///
+/// # Relative addresses
+///
+/// The A-instructions ```@$+n``` and ```@$-n``` load the ROM address `n` instructions after or
+/// before the current instruction, resolved immediately against `ins_ptr`; ```@$+0``` loads the
+/// address of the instruction itself. Labels are not instructions, so are not counted. They
+/// parse as `A3`, an `A1` which holds a ROM address, so objects can relocate them. A lone
+/// ```@$``` remains a reference to the symbol `$`. Relative addresses are fixed when parsed, so
+/// any tool which inserts or removes instructions between a relative reference and its target
+/// must rewrite the reference, or use a label instead.
+///
+/// # Literals
+///
+/// Besides decimal, A-instructions accept hexadecimal ```@0x1F```, binary ```@0b1010``` and
+/// character ```@'A'``` literals, loading the hex or binary value, or the ASCII code of the
+/// character. Any ASCII character may be quoted, including whitespace, `;` and comment
+/// characters. Values must fit the 15-bit constant of an A-instruction, as decimals must.
+///
 /// # Example
 ///
 /// ```
+/// # use std::collections::HashMap;
+/// # use n2t_assembler::parser::*;
 /// let mut sym_key_table = HashMap::new();
 /// let mut sym_val_table = vec![];
 /// assert_eq!(parse_ins("@123", 0, &mut sym_key_table, &mut sym_val_table), Ok(Some(Ins::A1{cint: 123})));
 /// assert_eq!(parse_ins("#comment\n", 0, &mut sym_key_table, &mut sym_val_table), Ok(None));
 /// ```
-pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, usize>,
-	sym_val_table: &mut Vec<(u16, SymUse)>) -> ParseResult {
+pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut SymKeyTable,
+	sym_val_table: &mut SymValTable) -> ParseResult {
 
 	enum DFA {
 		Start,
 		AOpen,
 		ASym,
 		AInt,
+		AHex,
+		ABin,
+		ACharOpen,
+		AChar,
+		ACharClose,
+		ARelSign,
+		ARel,
 		LFirst,
 		LClose,
 		LRest,
@@ -370,8 +560,8 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 
 	let mut dfa = DFA::Start;
 
-	let mne_buf_new = ||[' ' as u8; MNE_BUF_LEN];
-	let sym_buf_new = ||[' ' as u8; MAX_SYM_LEN];
+	let mne_buf_new = ||[b' '; MNE_BUF_LEN];
+	let sym_buf_new = ||[b' '; MAX_SYM_LEN];
 
 	let mut sb0 = sym_buf_new();
 	let mut mb0 = mne_buf_new();
@@ -383,6 +573,8 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 	let mut mi1 = 0usize;
 	let mut mi2 = 0usize;
 
+	let mut rel_neg = false;
+
 	fn push_sym_char(c: char, sb: &mut SymBuf, si: &mut usize) -> Result<(), ParseError> {
 		if *si == sb.len() {
 			return Err(ParseError::SymOverflow);
@@ -401,13 +593,10 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 		Ok(())
 	}
 
-	for (pos, c) in line.char_indices() {
-		if c.is_whitespace() {
+	for (pos, c, literal) in code_chars(line) {
+		if c.is_whitespace() && !literal {
 			continue;
 		}
-		if c == '#' || c == '/' {
-			break;
-		}
 		match dfa {
 			DFA::Start => {
 				match c {
@@ -429,6 +618,9 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 						dfa = DFA::ASym;
 						push_sym_char(c, &mut sb0, &mut si0)?;
 					},
+					'\'' => {
+						dfa = DFA::ACharOpen;
+					},
 					_ => return Err(ParseError::ExpectedFirstSymChar{found: c, pos})
 				}
 			},
@@ -437,17 +629,68 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 					'_'|'.'|'$'|':'|'a'..='z'|'A'..='Z'|'0'..='9' => {
 						push_sym_char(c, &mut sb0, &mut si0)?;
 					},
+					'+'|'-' if &sb0[..si0] == b"$" => {
+						dfa = DFA::ARelSign;
+						rel_neg = c == '-';
+						si0 = 0;
+					},
 					_ => return Err(ParseError::ExpectedSymChar{found: c, pos})
 				}
 			},
+			DFA::ARelSign | DFA::ARel => {
+				match c {
+					'0'..='9' => {
+						dfa = DFA::ARel;
+						push_sym_char(c, &mut sb0, &mut si0)?;
+					},
+					_ => return Err(ParseError::ExpectedDigit{found: c, pos})
+				}
+			},
 			DFA::AInt => {
 				match c {
 					'0'..='9' => {
 						push_sym_char(c, &mut sb0, &mut si0)?;
 					},
+					'x'|'X' if &sb0[..si0] == b"0" => {
+						dfa = DFA::AHex;
+						si0 = 0;
+					},
+					'b'|'B' if &sb0[..si0] == b"0" => {
+						dfa = DFA::ABin;
+						si0 = 0;
+					},
 					_ => return Err(ParseError::ExpectedDigit{found: c, pos})
 				}
 			},
+			DFA::AHex => {
+				match c {
+					'0'..='9'|'a'..='f'|'A'..='F' => {
+						push_sym_char(c, &mut sb0, &mut si0)?;
+					},
+					_ => return Err(ParseError::ExpectedDigit{found: c, pos})
+				}
+			},
+			DFA::ABin => {
+				match c {
+					'0'|'1' => {
+						push_sym_char(c, &mut sb0, &mut si0)?;
+					},
+					_ => return Err(ParseError::ExpectedDigit{found: c, pos})
+				}
+			},
+			DFA::ACharOpen => {
+				dfa = DFA::AChar;
+				push_sym_char(c, &mut sb0, &mut si0)?;
+			},
+			DFA::AChar => {
+				match c {
+					'\'' => dfa = DFA::ACharClose,
+					_ => return Err(ParseError::UnexpectedChar{found: c, pos})
+				}
+			},
+			DFA::ACharClose => {
+				return Err(ParseError::UnexpectedChar{found: c, pos})
+			},
 			DFA::LFirst => {
 				match c {
 					'_'|'.'|'$'|':'|'a'..='z'|'A'..='Z' => {
@@ -510,9 +753,49 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 			}
 			Ok(Some(Ins::A1{cint}))
 		},
+		DFA::AHex | DFA::ABin if si0 == 0 => {
+			Err(ParseError::AInsMissingArg)
+		},
+		DFA::AHex => {
+			let cint = u16::from_str_radix(unsafe {std::str::from_utf8_unchecked(&sb0[..si0])}, 16);
+			match cint {
+				Ok(cint) if cint <= MAX_INT_VAL => Ok(Some(Ins::A1{cint})),
+				_ => Err(ParseError::HexOverflow),
+			}
+		},
+		DFA::ABin => {
+			let cint = u16::from_str_radix(unsafe {std::str::from_utf8_unchecked(&sb0[..si0])}, 2);
+			match cint {
+				Ok(cint) if cint <= MAX_INT_VAL => Ok(Some(Ins::A1{cint})),
+				_ => Err(ParseError::BinOverflow),
+			}
+		},
+		DFA::ACharOpen => {
+			Err(ParseError::AInsMissingArg)
+		},
+		DFA::AChar => {
+			Err(ParseError::CharMissingClose)
+		},
+		DFA::ACharClose => {
+			Ok(Some(Ins::A1{cint: sb0[0] as u16}))
+		},
+		DFA::ARelSign => {
+			Err(ParseError::AInsMissingArg)
+		},
+		DFA::ARel => {
+			let offset = match unsafe {std::str::from_utf8_unchecked(&sb0[..si0])}.parse::<u16>() {
+				Ok(i) => i as i32,
+				Err(_) => return Err(ParseError::RelOutOfRange),
+			};
+			let address = if rel_neg { ins_ptr as i32 - offset } else { ins_ptr as i32 + offset };
+			if !(0..=MAX_INT_VAL as i32).contains(&address) {
+				return Err(ParseError::RelOutOfRange)
+			}
+			Ok(Some(Ins::A3{cint: address as u16}))
+		},
 		DFA::ASym => {
 			let sym = unsafe { std::str::from_utf8_unchecked(&sb0[..si0]) };
-			let sym_id = match sym_key_table.entry(String::from(sym.borrow())) {
+			let sym_id = match sym_key_table.entry(String::from(sym)) {
 				Entry::Occupied(entry) => {
 					*entry.get()
 				},
@@ -533,7 +816,7 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 		DFA::LClose => {
 			let sym = unsafe { std::str::from_utf8_unchecked(&sb0[..si0]) };
 			let sym_val = (ins_ptr, SymUse::LROM);
-			let sym_id = match sym_key_table.entry(String::from(sym.borrow())) {
+			let sym_id = match sym_key_table.entry(String::from(sym)) {
 				Entry::Occupied(entry) => {
 					let sym_id = *entry.get();
 					if sym_val_table[sym_id].1 == SymUse::LROM {
@@ -575,6 +858,7 @@ pub fn parse_ins(line: &str, ins_ptr: u16, sym_key_table: &mut HashMap<String, u
 #[cfg(test)]
 mod tests {
 	use std::collections::HashMap;
+	use std::borrow::Borrow;
 	use super::*;
 
 	#[test]
@@ -598,6 +882,59 @@ mod tests {
 		assert!(sym_val_table.is_empty());
 	}
 
+	#[test]
+	fn test_split_ins(){
+		// Single instructions should not be split, including C-instructions with a jump.
+		assert_eq!(split_ins("@1"), vec![(0, "@1")]);
+		assert_eq!(split_ins("D=A ; JMP"), vec![(0, "D=A ; JMP")]);
+		assert_eq!(split_ins("0;JMP // a;b"), vec![(0, "0;JMP // a;b")]);
+
+		// Any other ';' should separate instructions, reporting the position of each.
+		assert_eq!(split_ins("@5;D=A"), vec![(0, "@5"), (3, "D=A")]);
+		assert_eq!(split_ins("(L); @L ;0;JMP;D=M"), vec![(0, "(L)"), (4, " @L "), (9, "0;JMP"), (15, "D=M")]);
+		assert_eq!(split_ins("D;JGT;JMP"), vec![(0, "D;JGT"), (6, "JMP")]);
+		assert_eq!(split_ins("@1;"), vec![(0, "@1"), (3, "")]);
+
+		// Quoted characters should neither separate instructions nor start comments.
+		assert_eq!(split_ins("@';';D=A"), vec![(0, "@';'"), (5, "D=A")]);
+		assert_eq!(split_ins("@'#' # hash"), vec![(0, "@'#' # hash")]);
+
+		// Errors should be reported at their position in the whole line.
+		let mut sym_key_table = HashMap::new();
+		let mut sym_val_table = vec![];
+		let (offset, ins) = split_ins("@1;@2x")[1];
+		let e = parse_ins(ins, 0, &mut sym_key_table, &mut sym_val_table).unwrap_err().shifted(offset);
+		assert_eq!(e, ParseError::ExpectedDigit{found: 'x', pos: 5});
+	}
+
+	#[test]
+	fn test_parse_line(){
+		let mut sym_key_table = HashMap::new();
+		let mut sym_val_table = vec![];
+
+		// Instructions should span their text, less whitespace and comments.
+		let inss = parse_line("  (L) ; @$+0;0;JMP # done", 7, &mut sym_key_table, &mut sym_val_table);
+		assert_eq!(inss, vec![
+			Ok(Spanned{value: Ins::L1{sym_id: 0}, span: 2..5}),
+			Ok(Spanned{value: Ins::A3{cint: 7}, span: 8..12}),
+			Ok(Spanned{value: Ins::C3{comp: CompMne::Comp0, jump: JumpMne::JumpJmp}, span: 13..18}),
+		]);
+		let inss = parse_line("@'/' // slash", 0, &mut sym_key_table, &mut sym_val_table);
+		assert_eq!(inss, vec![Ok(Spanned{value: Ins::A1{cint: 47}, span: 0..4})]);
+
+		// Errors should span their offending character where known; the instruction pointer
+		// should advance past them.
+		let inss = parse_line("@1x; D+1 ;@$+0", 0, &mut sym_key_table, &mut sym_val_table);
+		assert_eq!(inss, vec![
+			Err(Spanned{value: ParseError::ExpectedDigit{found: 'x', pos: 2}, span: 2..3}),
+			Err(Spanned{value: ParseError::CInsNop, span: 5..8}),
+			Ok(Spanned{value: Ins::A3{cint: 2}, span: 10..14}),
+		]);
+
+		// Blank lines should parse to nothing.
+		assert!(parse_line("  // comment", 0, &mut sym_key_table, &mut sym_val_table).is_empty());
+	}
+
 	#[test]
 	fn test_ains_int_parsing(){
 		let mut sym_key_table = HashMap::new();
@@ -670,6 +1007,67 @@ mod tests {
 		assert!(sym_val_table.len() == TEST_SIZE);
 	}
 
+	#[test]
+	fn test_ains_relative_parsing(){
+		let mut sym_key_table = HashMap::new();
+		let mut sym_val_table = vec![];
+
+		// Relative addresses should be resolved against the instruction pointer.
+		assert_eq!(parse_ins("@$+3", 10, &mut sym_key_table, &mut sym_val_table), Ok(Some(Ins::A3{cint: 13})));
+		assert_eq!(parse_ins("@$-2", 10, &mut sym_key_table, &mut sym_val_table), Ok(Some(Ins::A3{cint: 8})));
+		assert_eq!(parse_ins("@ $ + 0 // here", 10, &mut sym_key_table, &mut sym_val_table), Ok(Some(Ins::A3{cint: 10})));
+		assert_eq!(parse_ins("@$-10", 10, &mut sym_key_table, &mut sym_val_table), Ok(Some(Ins::A3{cint: 0})));
+
+		// Addresses outside of ROM should be detected.
+		assert_eq!(parse_ins("@$-11", 10, &mut sym_key_table, &mut sym_val_table), Err(ParseError::RelOutOfRange));
+		assert_eq!(parse_ins("@$+32758", 10, &mut sym_key_table, &mut sym_val_table), Err(ParseError::RelOutOfRange));
+		assert_eq!(parse_ins("@$+99999", 10, &mut sym_key_table, &mut sym_val_table), Err(ParseError::RelOutOfRange));
+
+		// Malformed offsets should be detected.
+		assert_eq!(parse_ins("@$+", 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::AInsMissingArg));
+		assert_eq!(parse_ins("@$+x", 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::ExpectedDigit{found: 'x', pos: 3}));
+		assert_eq!(parse_ins("@$x+1", 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::ExpectedSymChar{found: '+', pos: 3}));
+		assert!(sym_key_table.is_empty());
+
+		// A lone '$' should remain a symbol.
+		assert_eq!(parse_ins("@$", 0, &mut sym_key_table, &mut sym_val_table), Ok(Some(Ins::A2{sym_id: 0})));
+	}
+
+	#[test]
+	fn test_ains_literal_parsing(){
+		let mut sym_key_table = HashMap::new();
+		let mut sym_val_table = vec![];
+		let mut parse = |ins: &str| parse_ins(ins, 0, &mut sym_key_table, &mut sym_val_table);
+
+		// Hex, binary and character literals should load their value.
+		assert_eq!(parse("@0x1F"), Ok(Some(Ins::A1{cint: 31})));
+		assert_eq!(parse("@0X7fff"), Ok(Some(Ins::A1{cint: 32767})));
+		assert_eq!(parse("@0b1010 // ten"), Ok(Some(Ins::A1{cint: 10})));
+		assert_eq!(parse("@0B0"), Ok(Some(Ins::A1{cint: 0})));
+		assert_eq!(parse("@'A'"), Ok(Some(Ins::A1{cint: 65})));
+		assert_eq!(parse("@ ' ' "), Ok(Some(Ins::A1{cint: 32})));
+		assert_eq!(parse("@'#'"), Ok(Some(Ins::A1{cint: 35})));
+		assert_eq!(parse("@'/' // slash"), Ok(Some(Ins::A1{cint: 47})));
+		assert_eq!(parse("@'''"), Ok(Some(Ins::A1{cint: 39})));
+
+		// Values should fit an A-instruction.
+		assert_eq!(parse("@0x8000"), Err(ParseError::HexOverflow));
+		assert_eq!(parse("@0x10000"), Err(ParseError::HexOverflow));
+		assert_eq!(parse("@0b1000000000000000"), Err(ParseError::BinOverflow));
+
+		// Malformed literals should be detected.
+		assert_eq!(parse("@0x"), Err(ParseError::AInsMissingArg));
+		assert_eq!(parse("@0b"), Err(ParseError::AInsMissingArg));
+		assert_eq!(parse("@0x1G"), Err(ParseError::ExpectedDigit{found: 'G', pos: 4}));
+		assert_eq!(parse("@0b102"), Err(ParseError::ExpectedDigit{found: '2', pos: 5}));
+		assert_eq!(parse("@1x1"), Err(ParseError::ExpectedDigit{found: 'x', pos: 2}));
+		assert_eq!(parse("@'"), Err(ParseError::AInsMissingArg));
+		assert_eq!(parse("@'A"), Err(ParseError::CharMissingClose));
+		assert_eq!(parse("@'AB'"), Err(ParseError::UnexpectedChar{found: 'B', pos: 3}));
+		assert_eq!(parse("@'A'B"), Err(ParseError::UnexpectedChar{found: 'B', pos: 4}));
+		assert!(sym_key_table.is_empty());
+	}
+
 	#[test]
 	fn test_malformed_ains(){
 		let mut sym_key_table = HashMap::new();
@@ -842,37 +1240,37 @@ mod tests {
 
 		// Jibberish dest should be detected as unknown.
 		let mut mne_type = Some(MneType::Dest);
-		let mut mne_buf = ['j' as u8, 'i' as u8, 'b' as u8, ' ' as u8];
+		let mut mne_buf = [b'j', b'i', b'b', b' '];
 		let mut ins = format!("jib={}", CompMne::CompNotD.as_str());
 		assert_eq!(parse_ins(&ins, 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::UnknownMne{mne_type, mne_buf}));
 
 		// Long jibberish dest should be detected as unknown.
 		mne_type = None;
-		mne_buf = ['j' as u8, 'i' as u8, 'b' as u8, 'b' as u8];
+		mne_buf = [b'j', b'i', b'b', b'b'];
 		ins = format!("jibberish={}", CompMne::CompNotD.as_str());
 		assert_eq!(parse_ins(&ins, 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::UnknownMne{mne_type, mne_buf}));
 
 		// Jibberish comp should be detected as unknown.
 		mne_type = Some(MneType::Comp);
-		mne_buf = ['j' as u8, 'i' as u8, 'b' as u8, ' ' as u8];
+		mne_buf = [b'j', b'i', b'b', b' '];
 		ins = format!("{}=jib", DestMne::DestD.as_str());
 		assert_eq!(parse_ins(&ins, 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::UnknownMne{mne_type, mne_buf}));
 
 		// Long jibberish comp should be detected as unknown.
 		mne_type = Some(MneType::Comp);
-		mne_buf = ['j' as u8, 'i' as u8, 'b' as u8, 'b' as u8];
+		mne_buf = [b'j', b'i', b'b', b'b'];
 		ins = format!("{}=jibberish", DestMne::DestD.as_str());
 		assert_eq!(parse_ins(&ins, 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::UnknownMne{mne_type, mne_buf}));
 
 		// Jibberish jump should be detected as unknown.
 		mne_type = Some(MneType::Jump);
-		mne_buf = ['j' as u8, 'i' as u8, 'b' as u8, ' ' as u8];
+		mne_buf = [b'j', b'i', b'b', b' '];
 		ins = format!("{}={};jib", DestMne::DestD.as_str(), CompMne::CompM.as_str());
 		assert_eq!(parse_ins(&ins, 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::UnknownMne{mne_type, mne_buf}));
 
 		// Long jibberish jump should be detected as unknown.
 		mne_type = Some(MneType::Jump);
-		mne_buf = ['j' as u8, 'i' as u8, 'b' as u8, 'b' as u8];
+		mne_buf = [b'j', b'i', b'b', b'b'];
 		ins = format!("{}={};jibberish", DestMne::DestD.as_str(), CompMne::CompM.as_str());
 		assert_eq!(parse_ins(&ins, 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::UnknownMne{mne_type, mne_buf}));
 
@@ -888,7 +1286,7 @@ mod tests {
 
 		// Stand-along comp c-instructions have no effect and should be detected.
 		for comp in all::<CompMne>().collect::<Vec<_>>() {
-			let ins = format!("{}", comp.as_str());
+			let ins = comp.as_str().to_string();
 			assert_eq!(parse_ins(&ins, 0, &mut sym_key_table, &mut sym_val_table), Err(ParseError::CInsNop));
 		}
 
@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+
+#[derive(Debug, PartialEq)]
+pub enum DirectiveError {
+	UnknownDirective,
+	IfMissingSym,
+	UnmatchedElse,
+	DuplicateElse,
+	UnmatchedEndif,
+	Unterminated{line: String, line_num: u32},
+	LinkageMissingSym,
+	ExternNotLinked,
+	ExternDefined,
+	GlobalPredefined,
+}
+
+impl DirectiveError {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			DirectiveError::UnknownDirective => "Unknown directive! Expected one of '.if', '.else', '.endif', '.macro', '.endmacro', '.global' or '.extern'.",
+			DirectiveError::IfMissingSym => "Expected a single symbol after '.if'.",
+			DirectiveError::UnmatchedElse => "Found '.else' outside of an '.if' block.",
+			DirectiveError::DuplicateElse => "Found a second '.else' in the same '.if' block.",
+			DirectiveError::UnmatchedEndif => "Found '.endif' outside of an '.if' block.",
+			DirectiveError::Unterminated{..} => "Expected '.endif' to close '.if' block before the end of the file.",
+			DirectiveError::LinkageMissingSym => "Expected a single symbol after '.global' or '.extern'.",
+			DirectiveError::ExternNotLinked => "Found '.extern' outside of an object! Assemble with '--object' and link the objects.",
+			DirectiveError::ExternDefined => "Symbol declared '.extern' is defined here; declare it '.global' instead.",
+			DirectiveError::GlobalPredefined => "Predefined symbols cannot be declared '.global'.",
+		}
+	}
+}
+
+struct Block {
+	line: String,
+	line_num: u32,
+	enclosing_active: bool,
+	condition: bool,
+	has_else: bool,
+}
+
+/// Conditional assembly; lines within `.if SYMBOL`, `.else` and `.endif` directives are only
+/// assembled when the condition holds, where a condition holds if its symbol is defined. Blocks
+/// may nest. Directives must be the only thing on their line, other than a comment.
+///
+/// ```text
+/// .if DEBUG
+/// @ASSERT_FAILED
+/// D;JNE
+/// .endif
+/// ```
+pub struct Conditionals<'a> {
+	defines: &'a HashSet<String>,
+	blocks: Vec<Block>,
+}
+
+impl<'a> Conditionals<'a> {
+	pub fn new(defines: &'a HashSet<String>) -> Self {
+		Conditionals{defines, blocks: vec![]}
+	}
+
+	fn is_active(&self) -> bool {
+		self.blocks.last().is_none_or(|b| b.enclosing_active && b.condition != b.has_else)
+	}
+
+	/// Process a line; returns whether the line should be assembled. Directives are never
+	/// assembled.
+	pub fn filter(&mut self, line: &str, line_num: u32) -> Result<bool, DirectiveError> {
+		let text = line.split(['#', '/']).next().unwrap().trim();
+		let directive = match text.strip_prefix('.') {
+			Some(directive) => directive,
+			None => return Ok(self.is_active()),
+		};
+		let mut words = directive.split_whitespace();
+		match (words.next(), words.next(), words.next()) {
+			(Some("if"), Some(sym), None) => {
+				let block = Block{line: line.to_string(), line_num, enclosing_active: self.is_active(),
+					condition: self.defines.contains(sym), has_else: false};
+				self.blocks.push(block);
+			},
+			(Some("if"), _, _) => return Err(DirectiveError::IfMissingSym),
+			(Some("else"), None, _) => match self.blocks.last_mut() {
+				None => return Err(DirectiveError::UnmatchedElse),
+				Some(block) if block.has_else => return Err(DirectiveError::DuplicateElse),
+				Some(block) => block.has_else = true,
+			},
+			(Some("endif"), None, _) => {
+				if self.blocks.pop().is_none() {
+					return Err(DirectiveError::UnmatchedEndif);
+				}
+			},
+			// Left for [`Macros`](crate::macros::Macros) and [`Linkage`].
+			(Some("macro" | "endmacro" | "global" | "extern"), _, _) => return Ok(self.is_active()),
+			_ => return Err(DirectiveError::UnknownDirective),
+		}
+		Ok(false)
+	}
+
+	/// Check every block was closed, at the end of the input.
+	pub fn finish(&mut self) -> Result<(), DirectiveError> {
+		match self.blocks.pop() {
+			Some(block) => Err(DirectiveError::Unterminated{line: block.line, line_num: block.line_num}),
+			None => Ok(()),
+		}
+	}
+}
+
+/// A symbol declared by a linkage directive.
+pub struct Decl {
+	pub sym: String,
+	pub line: String,
+	pub line_num: u32,
+}
+
+/// Linkage between separately assembled objects; `.global SYMBOL` exports a label or variable
+/// of this object, and `.extern SYMBOL` imports one exported by another object. See
+/// [`crate::object`]. Directives must be the only thing on their line, other than a comment.
+///
+/// ```text
+/// .extern Math.multiply
+/// .global main
+/// (main)
+/// @Math.multiply
+/// 0;JMP
+/// ```
+pub struct Linkage {
+	object: bool,
+	pub globals: Vec<Decl>,
+	pub externs: Vec<Decl>,
+}
+
+impl Linkage {
+	/// `object` is whether the lines are assembled as an object; only objects may have externs.
+	pub fn new(object: bool) -> Self {
+		Linkage{object, globals: vec![], externs: vec![]}
+	}
+
+	/// Process a line which is to be assembled; returns whether it was a linkage directive.
+	pub fn filter(&mut self, line: &str, line_num: u32) -> Result<bool, DirectiveError> {
+		let text = line.split(['#', '/']).next().unwrap().trim();
+		let mut words = text.split_whitespace();
+		let decls = match words.next() {
+			Some(".global") => &mut self.globals,
+			Some(".extern") if self.object => &mut self.externs,
+			Some(".extern") => return Err(DirectiveError::ExternNotLinked),
+			_ => return Ok(false),
+		};
+		match (words.next(), words.next()) {
+			(Some(sym), None) => decls.push(Decl{sym: sym.to_string(), line: line.to_string(), line_num}),
+			_ => return Err(DirectiveError::LinkageMissingSym),
+		}
+		Ok(true)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_conditionals(){
+		let defines = HashSet::from(["DEBUG".to_string()]);
+		let assembled = |text: &str| {
+			let mut conditionals = Conditionals::new(&defines);
+			let mut lines = vec![];
+			for (i, line) in text.lines().enumerate() {
+				if conditionals.filter(line, i as u32 + 1).unwrap() {
+					lines.push(line.to_string());
+				}
+			}
+			conditionals.finish().unwrap();
+			lines
+		};
+
+		// Blocks should be assembled only when their condition holds.
+		assert_eq!(assembled("@1\n.if DEBUG\n@2\n.else\n@3\n.endif\n@4"), vec!["@1", "@2", "@4"]);
+		assert_eq!(assembled(".if RELEASE // comment\n@2\n.else\n@3\n.endif"), vec!["@3"]);
+
+		// Nested blocks should only be assembled when every enclosing block is.
+		assert_eq!(assembled(".if RELEASE\n.if DEBUG\n@1\n.else\n@2\n.endif\n.else\n\t.if DEBUG\n@3\n\t.endif\n.endif"), vec!["@3"]);
+
+		// Malformed directives should be detected.
+		let error = |text: &str| {
+			let mut conditionals = Conditionals::new(&defines);
+			for (i, line) in text.lines().enumerate() {
+				conditionals.filter(line, i as u32 + 1)?;
+			}
+			conditionals.finish()
+		};
+		assert_eq!(error(".ifdef DEBUG"), Err(DirectiveError::UnknownDirective));
+		assert_eq!(error(".if"), Err(DirectiveError::IfMissingSym));
+		assert_eq!(error(".if A B"), Err(DirectiveError::IfMissingSym));
+		assert_eq!(error(".else"), Err(DirectiveError::UnmatchedElse));
+		assert_eq!(error(".if A\n.else\n.else"), Err(DirectiveError::DuplicateElse));
+		assert_eq!(error(".endif"), Err(DirectiveError::UnmatchedEndif));
+		assert_eq!(error("@1\n.if A\n.if B\n.endif"), Err(DirectiveError::Unterminated{line: ".if A".to_string(), line_num: 2}));
+
+		// Linkage directives should be left for the assembler, unless excluded.
+		assert_eq!(assembled(".global main\n.if RELEASE\n.extern Sys.init\n.endif"), vec![".global main"]);
+	}
+
+	#[test]
+	fn test_linkage(){
+		let mut linkage = Linkage::new(true);
+		assert_eq!(linkage.filter(".global main // entry", 1), Ok(true));
+		assert_eq!(linkage.filter("\t.extern Sys.init", 2), Ok(true));
+		assert_eq!(linkage.filter("@main", 3), Ok(false));
+		assert_eq!(linkage.filter(".global", 4), Err(DirectiveError::LinkageMissingSym));
+		assert_eq!(linkage.filter(".extern A B", 5), Err(DirectiveError::LinkageMissingSym));
+		let syms = |decls: &[Decl]| decls.iter().map(|d| (d.sym.clone(), d.line_num)).collect::<Vec<_>>();
+		assert_eq!(syms(&linkage.globals), vec![("main".to_string(), 1)]);
+		assert_eq!(syms(&linkage.externs), vec![("Sys.init".to_string(), 2)]);
+
+		// Externs should only be resolved by linking objects.
+		assert_eq!(Linkage::new(false).filter(".extern Sys.init", 1), Err(DirectiveError::ExternNotLinked));
+	}
+}
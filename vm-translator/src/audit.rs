@@ -0,0 +1,328 @@
+use std::io::Write;
+use core::ops::Range;
+use compact_str::CompactString;
+use crate::coder::*;
+use crate::errors::CodeError;
+use crate::parser::VmIns;
+use crate::tokenizer::VmSeg;
+use crate::machine::Machine;
+use n2t_assembler::generator::Rng;
+
+const AUDIT_FILE: &'static str = "Audit";
+const CALLER: &'static str = "Audit.main";
+const CALLEE: &'static str = "Audit.callee";
+const END_LABEL: &'static str = "__AUDIT_END";
+const CALLEE_RESULT: u16 = 4242;
+const MAX_STEPS: usize = 10_000;
+const STATE_COUNT: u64 = 8;
+
+// Initial segments, laid out on the stack as on entry to a function.
+const ARG_BASE: i16 = 256;
+const LCL_BASE: i16 = 264;
+const SP_BASE: i16 = 272;
+const THIS_BASE: i16 = 3000;
+const THAT_BASE: i16 = 3016;
+
+/// RAM compared after each run; covers the stack and the this/that segments.
+const CHECKED_RAM_SIZE: usize = 4096;
+const STACK_END: usize = 2048;
+const SCRATCH_REGISTERS: Range<usize> = 13..16;
+/// Static variables are the first variables allocated; each case addresses at most one.
+const STATIC_ADDRESS: usize = 16;
+
+/// An instruction template whose execution did not match the VM specification.
+pub struct AuditFailure {
+	pub case: String,
+	pub msg: String,
+	/// The case is a comparison which failed only on operands whose difference overflows.
+	pub known: bool,
+}
+
+/// The effect of a case on RAM according to the VM specification.
+type Effect = fn(&mut [i16], &[VmIns]);
+
+struct Case {
+	inss: Vec<VmIns>,
+	effect: Effect,
+}
+
+fn push(ram: &mut [i16], value: i16) {
+	let sp = ram[0] as usize;
+	ram[sp] = value;
+	ram[0] += 1;
+}
+
+fn pop(ram: &mut [i16]) -> i16 {
+	ram[0] -= 1;
+	ram[ram[0] as usize]
+}
+
+fn binary(ram: &mut [i16], op: impl Fn(i16, i16) -> i16) {
+	let y = pop(ram);
+	let x = pop(ram);
+	push(ram, op(x, y));
+}
+
+fn unary(ram: &mut [i16], op: impl Fn(i16) -> i16) {
+	let x = pop(ram);
+	push(ram, op(x));
+}
+
+fn segment_address(ram: &[i16], segment: VmSeg, index: u16) -> usize {
+	let base = |pointer: usize| ram[pointer] as u16 as usize;
+	let index = index as usize;
+	match segment {
+		VmSeg::Local => base(1) + index,
+		VmSeg::Argument => base(2) + index,
+		VmSeg::This => base(3) + index,
+		VmSeg::That => base(4) + index,
+		VmSeg::Pointer => 3 + index,
+		VmSeg::Temp => 5 + index,
+		VmSeg::Static => STATIC_ADDRESS,
+		VmSeg::Constant => unreachable!(),
+	}
+}
+
+/// Apply instructions as the VM specification defines them. Calls are to a function which
+/// returns [`CALLEE_RESULT`]; control flow is not modelled.
+fn spec(ram: &mut [i16], inss: &[VmIns]) {
+	let truth = |b: bool| if b { -1 } else { 0 };
+	for ins in inss {
+		match ins {
+			VmIns::Push{segment: VmSeg::Constant, index} => push(ram, *index as i16),
+			VmIns::Push{segment, index} => {
+				let value = ram[segment_address(ram, *segment, *index)];
+				push(ram, value);
+			},
+			VmIns::Pop{segment: VmSeg::Constant, ..} => {
+				pop(ram);
+			},
+			VmIns::Pop{segment, index} => {
+				let value = pop(ram);
+				ram[segment_address(ram, *segment, *index)] = value;
+			},
+			VmIns::Inc{segment, index} => {
+				let address = segment_address(ram, *segment, *index);
+				ram[address] = ram[address].wrapping_add(1);
+			},
+			VmIns::Dec{segment, index} => {
+				let address = segment_address(ram, *segment, *index);
+				ram[address] = ram[address].wrapping_sub(1);
+			},
+			VmIns::Function{locals_count, ..} => {
+				for _ in 0..*locals_count {
+					push(ram, 0);
+				}
+			},
+			VmIns::Call{args_count, ..} => {
+				for _ in 0..*args_count {
+					pop(ram);
+				}
+				push(ram, CALLEE_RESULT as i16);
+			},
+			VmIns::Add => binary(ram, |x, y| x.wrapping_add(y)),
+			VmIns::Sub => binary(ram, |x, y| x.wrapping_sub(y)),
+			VmIns::Neg => unary(ram, |x| x.wrapping_neg()),
+			VmIns::And => binary(ram, |x, y| x & y),
+			VmIns::Or => binary(ram, |x, y| x | y),
+			VmIns::Not => unary(ram, |x| !x),
+			VmIns::Eq => binary(ram, |x, y| truth(x == y)),
+			VmIns::Lt => binary(ram, |x, y| truth(x < y)),
+			VmIns::Gt => binary(ram, |x, y| truth(x > y)),
+			_ => unreachable!("{} is not modelled", ins),
+		}
+	}
+}
+
+/// The effect of the branch case; pushes 1 if the condition holds, otherwise 0.
+fn branch(ram: &mut [i16], _: &[VmIns]) {
+	let condition = pop(ram);
+	push(ram, (condition != 0) as i16);
+}
+
+fn cases() -> Vec<Case> {
+	let mut cases = vec![];
+	let mut case = |inss: Vec<VmIns>| cases.push(Case{inss, effect: spec});
+	for index in [0, 1, 2, 1234] {
+		case(vec![VmIns::Push{segment: VmSeg::Constant, index}]);
+	}
+	case(vec![VmIns::Pop{segment: VmSeg::Constant, index: 0}]);
+	let segments: [(VmSeg, &[u16]); 7] = [
+		(VmSeg::Local, &[0, 1, 2, 5]),
+		(VmSeg::Argument, &[0, 1, 2, 5]),
+		(VmSeg::This, &[0, 1, 2, 5]),
+		(VmSeg::That, &[0, 1, 2, 5]),
+		(VmSeg::Temp, &[0, 1, 7]),
+		(VmSeg::Pointer, &[0, 1]),
+		(VmSeg::Static, &[0, 3]),
+	];
+	for (segment, indices) in segments {
+		for &index in indices {
+			case(vec![VmIns::Push{segment, index}]);
+			case(vec![VmIns::Pop{segment, index}]);
+			case(vec![VmIns::Inc{segment, index}]);
+			case(vec![VmIns::Dec{segment, index}]);
+		}
+	}
+	for ins in [VmIns::Add, VmIns::Sub, VmIns::Neg, VmIns::And, VmIns::Or, VmIns::Not, VmIns::Eq, VmIns::Lt, VmIns::Gt] {
+		case(vec![ins]);
+	}
+	for locals_count in [0, 1, 2, 5, 9] {
+		case(vec![VmIns::Function{name: CompactString::from("Audit.entered"), locals_count}]);
+	}
+	for args_count in [0, 1, 3] {
+		case(vec![VmIns::Call{function: CompactString::from(CALLEE), args_count}]);
+	}
+	let label = |label: &str| CompactString::from(label);
+	cases.push(Case{inss: vec![
+		VmIns::IfGoto{label: label("TRUE")},
+		VmIns::Push{segment: VmSeg::Constant, index: 0},
+		VmIns::Goto{label: label("END")},
+		VmIns::Label{label: label("TRUE")},
+		VmIns::Push{segment: VmSeg::Constant, index: 1},
+		VmIns::Label{label: label("END")},
+	], effect: branch});
+	cases
+}
+
+/// Operands, `x` and `y`, on top of the stack of the overflow states; the difference of each
+/// pair overflows, and `lt`/`gt`, which subtract their operands, give the wrong result.
+const OVERFLOW_OPERANDS: [(i16, i16); 2] = [(i16::MIN, 1), (i16::MAX, -1)];
+
+/// Initial RAM of each run; random values small enough that comparisons do not overflow,
+/// followed by the overflow states.
+fn initial_states() -> Vec<Vec<i16>> {
+	let top = SP_BASE as usize - 1;
+	let state = |seed: u64| {
+		let mut rng = Rng::new(seed);
+		let mut ram: Vec<i16> = (0..CHECKED_RAM_SIZE).map(|_| rng.below(32768) as i16 - 16384).collect();
+		ram[0..5].copy_from_slice(&[SP_BASE, LCL_BASE, ARG_BASE, THIS_BASE, THAT_BASE]);
+		ram
+	};
+	let mut states: Vec<Vec<i16>> = (0..STATE_COUNT).map(|seed| {
+		let mut ram = state(seed);
+		match seed {
+			0 => ram[top] = ram[top - 1],
+			1 => ram[top] = 0,
+			_ => (),
+		}
+		ram
+	}).collect();
+	for (seed, (x, y)) in (STATE_COUNT..).zip(OVERFLOW_OPERANDS) {
+		let mut ram = state(seed);
+		ram[top - 1] = x;
+		ram[top] = y;
+		states.push(ram);
+	}
+	states
+}
+
+fn write_inss(coder: &mut Coder, out: &mut Vec<u8>, ctx: &mut InsContext, inss: &[VmIns]) -> Result<(), CodeError> {
+	for ins in inss {
+		if let VmIns::Function{name, ..} = ins {
+			ctx.vm_function_name = name.clone();
+		}
+		coder.write_vm_ins(out, ins.clone(), ctx)?;
+	}
+	Ok(())
+}
+
+/// Translate a case followed by a callee and the core implementation, then run the case alone.
+fn run_case(options: &CoderOptions, case: &Case, ram: &[i16]) -> Result<(), String> {
+	let mut coder = Coder::new(options.clone());
+	let mut ctx = InsContext::new();
+	ctx.vm_file_name = CompactString::from(AUDIT_FILE);
+	ctx.vm_function_name = CompactString::from(CALLER);
+	let callee = [
+		VmIns::Function{name: CompactString::from(CALLEE), locals_count: 2},
+		VmIns::Push{segment: VmSeg::Constant, index: CALLEE_RESULT},
+		VmIns::Return,
+	];
+	let mut out = vec![];
+	let translation = write_inss(&mut coder, &mut out, &mut ctx, &case.inss)
+		.and_then(|_| Ok(write!(out, "({})\n@{}\n0;JMP\n", END_LABEL, END_LABEL)?))
+		.and_then(|_| write_inss(&mut coder, &mut out, &mut ctx, &callee))
+		.and_then(|_| coder.write_core_impl(&mut out))
+		.and_then(|_| coder.write_dispatch_impl(&mut out))
+		.and_then(|_| coder.write_module_init_impl(&mut out))
+		.and_then(|_| coder.write_pruned_core_impl(&mut out));
+	if let Err(e) = translation {
+		return Err(format!("translation failed; {:?}", e));
+	}
+
+	let mut machine = Machine::new(&String::from_utf8(out).unwrap()).map_err(|e| e.to_string())?;
+	machine.ram[..ram.len()].copy_from_slice(ram);
+	machine.run_until(END_LABEL, MAX_STEPS).map_err(|e| e.to_string())?;
+
+	let mut expected = ram.to_vec();
+	(case.effect)(&mut expected, &case.inss);
+	let sp = expected[0];
+	if machine.ram[0] != sp {
+		return Err(format!("net stack effect is {}, expected {}", machine.ram[0] - ram[0], sp - ram[0]));
+	}
+	let counters: Vec<usize> = coder.get_counters().iter().map(|(address, _)| *address as usize).collect();
+	for (address, (actual, expected)) in machine.ram.iter().zip(&expected).enumerate() {
+		let is_scratch = SCRATCH_REGISTERS.contains(&address) || (sp as usize..STACK_END).contains(&address)
+			|| counters.contains(&address);
+		if !is_scratch && actual != expected {
+			return Err(format!("RAM[{}] is {}, expected {}", address, actual, expected));
+		}
+	}
+	Ok(())
+}
+
+/// Translate each instruction template, execute it on a model of the Hack computer from a
+/// number of random initial states, and compare the RAM it leaves to that expected by the VM
+/// specification. Catches templates which leave the stack pointer, segments or any other
+/// memory in the wrong state.
+///
+/// The `lt` and `gt` templates compare by the sign of `x - y`, which is wrong when the
+/// subtraction overflows; their failures on the overflow states are reported as known.
+pub fn audit(options: &CoderOptions) -> Vec<AuditFailure> {
+	let states = initial_states();
+	let (random_states, overflow_states) = states.split_at(STATE_COUNT as usize);
+	let mut failures = vec![];
+	for case in cases() {
+		let is_comparison = matches!(case.inss[..], [VmIns::Lt] | [VmIns::Gt]);
+		let failure = random_states.iter().find_map(|ram| run_case(options, &case, ram).err()).map(|msg| (msg, false))
+			.or_else(|| overflow_states.iter().find_map(|ram| run_case(options, &case, ram).err()).map(|msg| (msg, is_comparison)));
+		if let Some((msg, known)) = failure {
+			let case = case.inss.iter().map(|ins| ins.to_string()).collect::<Vec<_>>().join("; ");
+			failures.push(AuditFailure{case, msg, known});
+		}
+	}
+	failures
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_templates_pass_audit(){
+		let options = [
+			CoderOptions::default(),
+			CoderOptions{prune_core: true, no_module_init: true, ..CoderOptions::default()},
+			CoderOptions{instrument_base: Some(1000), ..CoderOptions::default()},
+			CoderOptions{templates: TemplateSet::Speed, ..CoderOptions::default()},
+		];
+		for options in options {
+			let (known, failures): (Vec<_>, Vec<_>) = audit(&options).into_iter().partition(|f| f.known);
+			let report: Vec<String> = failures.iter().map(|f| format!("{}: {}", f.case, f.msg)).collect();
+			assert!(failures.is_empty(), "{:?}\n{}", options, report.join("\n"));
+			let known: Vec<String> = known.into_iter().map(|f| f.case).collect();
+			assert_eq!(known, ["lt", "gt"], "{:?}", options);
+		}
+	}
+
+	#[test]
+	fn test_audit_detects_wrong_effects(){
+		// A case whose effect disagrees with its translation should fail on every state.
+		let case = Case{inss: vec![VmIns::Push{segment: VmSeg::Constant, index: 7}], effect: |ram, _| push(ram, 8)};
+		let msg = run_case(&CoderOptions::default(), &case, &initial_states()[2]).unwrap_err();
+		assert_eq!(msg, format!("RAM[{}] is 7, expected 8", SP_BASE));
+		let case = Case{inss: vec![VmIns::Add], effect: |_, _| ()};
+		let msg = run_case(&CoderOptions::default(), &case, &initial_states()[2]).unwrap_err();
+		assert_eq!(msg, "net stack effect is -1, expected 0");
+	}
+}
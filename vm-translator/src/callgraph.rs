@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use compact_str::CompactString;
+
+/// Wraps an output and counts the Hack instructions written through it; label
+/// declarations and blank lines are not instructions.
+pub struct InsCounter<W: Write> {
+	out: W,
+	count: usize,
+	line_start: bool,
+	line_is_ins: bool,
+}
+
+impl<W: Write> InsCounter<W> {
+	pub fn new(out: W) -> Self {
+		InsCounter{out, count: 0, line_start: true, line_is_ins: false}
+	}
+
+	pub fn count(&self) -> usize {
+		self.count
+	}
+}
+
+impl<W: Write> Write for InsCounter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let n = self.out.write(buf)?;
+		for c in &buf[..n] {
+			match c {
+				b'\n' => {
+					if self.line_is_ins {
+						self.count += 1;
+					}
+					self.line_start = true;
+					self.line_is_ins = false;
+				},
+				c if c.is_ascii_whitespace() => (),
+				c => {
+					if self.line_start {
+						self.line_is_ins = *c != b'(';
+					}
+					self.line_start = false;
+				},
+			}
+		}
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+}
+
+#[derive(Default)]
+struct FunctionNode {
+	ins_count: usize,
+	calls: BTreeMap<CompactString, usize>,
+}
+
+/// The VM level call graph of a program, with the number of Hack instructions emitted
+/// for each function.
+#[derive(Default)]
+pub struct CallGraph {
+	functions: BTreeMap<CompactString, FunctionNode>,
+}
+
+impl CallGraph {
+	pub fn new() -> Self {
+		CallGraph::default()
+	}
+
+	pub fn add_function(&mut self, name: &str) {
+		self.functions.entry(CompactString::from(name)).or_default();
+	}
+
+	pub fn add_call(&mut self, caller: &str, callee: &str) {
+		let node = self.functions.entry(CompactString::from(caller)).or_default();
+		*node.calls.entry(CompactString::from(callee)).or_insert(0) += 1;
+	}
+
+	pub fn add_ins_count(&mut self, function: &str, ins_count: usize) {
+		self.functions.entry(CompactString::from(function)).or_default().ins_count += ins_count;
+	}
+
+	/// Total instructions of a function and every function reachable from it.
+	pub fn reachable_ins_count(&self, function: &str) -> usize {
+		let mut visited = BTreeSet::new();
+		let mut stack = vec![function];
+		let mut total = 0;
+		while let Some(name) = stack.pop() {
+			if !visited.insert(name) {
+				continue;
+			}
+			if let Some(node) = self.functions.get(name) {
+				total += node.ins_count;
+				stack.extend(node.calls.keys().map(|callee| callee.as_str()));
+			}
+		}
+		total
+	}
+
+	/// Render the graph in graphviz DOT format. Functions are annotated with their own
+	/// and their cumulative reachable instruction counts, calls with their call site count.
+	/// Functions which are called but never defined are drawn dashed.
+	pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		writeln!(out, "digraph callgraph {{")?;
+		writeln!(out, "\tnode [shape=box];")?;
+		for (name, node) in &self.functions {
+			writeln!(out, "\t\"{}\" [label=\"{}\\n{} ins / {} reachable\"];",
+				name, name, node.ins_count, self.reachable_ins_count(name))?;
+		}
+		let undefined: BTreeSet<_> = self.functions.values()
+			.flat_map(|node| node.calls.keys())
+			.filter(|callee| !self.functions.contains_key(*callee))
+			.collect();
+		for name in undefined {
+			writeln!(out, "\t\"{}\" [style=dashed];", name)?;
+		}
+		for (name, node) in &self.functions {
+			for (callee, count) in &node.calls {
+				writeln!(out, "\t\"{}\" -> \"{}\" [label=\"{}\"];", name, callee, count)?;
+			}
+		}
+		writeln!(out, "}}")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ins_counter(){
+		let mut counter = InsCounter::new(vec![]);
+		write!(counter, "(LABEL)\n@SP\nAM=M+1\n").unwrap();
+		write!(counter, "\n  (INDENTED)\n").unwrap();
+		write!(counter, "A=A-1\nM=").unwrap();
+		write!(counter, "0\n").unwrap();
+		assert_eq!(counter.count(), 4);
+	}
+
+	#[test]
+	fn test_reachable_ins_count(){
+		let mut graph = CallGraph::new();
+		graph.add_ins_count("Main.main", 10);
+		graph.add_ins_count("Foo.a", 5);
+		graph.add_ins_count("Foo.b", 3);
+		graph.add_call("Main.main", "Foo.a");
+		graph.add_call("Main.main", "Foo.a");
+		graph.add_call("Foo.a", "Foo.b");
+		graph.add_call("Foo.b", "Foo.a"); // mutual recursion is only counted once
+		graph.add_call("Foo.b", "Math.multiply"); // undefined functions have no size
+
+		assert_eq!(graph.reachable_ins_count("Main.main"), 18);
+		assert_eq!(graph.reachable_ins_count("Foo.a"), 8);
+		assert_eq!(graph.reachable_ins_count("Foo.b"), 8);
+
+		let mut dot = vec![];
+		graph.write_dot(&mut dot).unwrap();
+		let dot = String::from_utf8(dot).unwrap();
+		assert!(dot.contains("\"Main.main\" [label=\"Main.main\\n10 ins / 18 reachable\"];"));
+		assert!(dot.contains("\"Main.main\" -> \"Foo.a\" [label=\"2\"];"));
+		assert!(dot.contains("\"Math.multiply\" [style=dashed];"));
+	}
+
+	#[test]
+	fn test_function_labels(){
+		// Functions should be keyed by their function label, so unqualified names are qualified by their file.
+		use std::path::PathBuf;
+		use crate::{translate, VmSource};
+		use crate::coder::{Coder, CoderOptions};
+		use crate::errors::TranslationContext;
+		use crate::parser::ParserOptions;
+		let sources = vec![VmSource::Virtual{path: PathBuf::from("Main.vm"),
+			code: "function f 0\ncall g 0\nreturn\nfunction g 0\ncall Sys.halt 0\nreturn\n".to_string()}];
+		let mut graph = CallGraph::new();
+		assert!(translate(sources, vec![], &mut TranslationContext::new(), &mut Coder::new(CoderOptions::default()), &mut graph,
+			ParserOptions::default()).is_ok());
+		let mut dot = vec![];
+		graph.write_dot(&mut dot).unwrap();
+		let dot = String::from_utf8(dot).unwrap();
+		assert!(dot.contains("\"Main.f\" -> \"Main.g\" [label=\"1\"];"), "{}", dot);
+		assert!(dot.contains("\"Main.g\" -> \"Sys.halt\" [label=\"1\"];"), "{}", dot);
+		assert!(graph.reachable_ins_count("Main.f") > graph.reachable_ins_count("Main.g"));
+		assert_eq!(graph.reachable_ins_count("f"), 0);
+	}
+}
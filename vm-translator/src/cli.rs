@@ -1,6 +1,10 @@
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{CommandFactory, Parser};
+use std::path::{Path, PathBuf};
+use std::io::Read;
 use std::fs;
+use n2t_vm_translator::VmSource;
+use n2t_vm_translator::coder::{CoderOptions, TemplateSet};
+use n2t_vm_translator::parser::ParserOptions;
 
 const ABOUT_HELP: &'static str = "\
 Translate intermediate Hack platform VM code to assembly. Input is a set of 
@@ -13,16 +17,109 @@ struct ClapArgs {
 	input: Vec<PathBuf>,
 	#[arg(name = "output", short, long, help = "path to output assembly", default_value = "out.asm")]
 	output: String,
+	#[arg(long, conflicts_with = "input", help = "read code from stdin; '//// FILE: Name.vm' lines delimit files")]
+	stdin: bool,
+	#[arg(long, value_enum, help = "preset of translation options; combined with any options given explicitly. \
+		Profiles only select the options listed; debug does not yet add checked memory access or annotations \
+		(see vm-translator/doc/profiles.md)")]
+	profile: Option<Profile>,
+	#[arg(long, value_enum, help = "additional output/s; written beside the output assembly")]
+	emit: Vec<Emit>,
+	#[arg(long, help = "count function entries in RAM; writes the counter map beside the output assembly (.counters)")]
+	instrument: bool,
+	#[arg(long, help = "RAM address of the first instrumentation counter; required to instrument, as no RAM is free \
+		of every program (the Jack OS heap spans 2048-16383), so pick addresses the program never uses")]
+	instrument_base: Option<u16>,
+	#[arg(long, help = "do not call module initializers ('File.init__static' functions) before Sys.init")]
+	no_module_init: bool,
+	#[arg(long, help = "omit core subroutines (eq, gt, lt and return) which the input code never uses")]
+	prune_core: bool,
+	#[arg(long, value_enum, help = "favour smaller or faster code where instructions have specialised forms [default: size]")]
+	optimize: Option<Optimize>,
+	#[arg(long, help = "enforce the official vm specification; identifier characters and 'File.name' function naming")]
+	strict: bool,
+	#[arg(long, conflicts_with = "strict", help = "enable vm language extensions; call-indirect, inc and dec")]
+	ext: bool,
+	#[arg(long, help = "check each instruction template by executing it on a model of the hack computer; lt and gt are known to fail when x - y overflows")]
+	audit: bool,
+	#[arg(long, help = "accept 'pop constant i', discarding the top of the stack")]
+	allow_pop_constant: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, clap::ValueEnum)]
+pub enum Profile {
+	/// instrument function entries (--instrument); requires --instrument-base
+	Debug,
+	/// omit unused core subroutines and favour faster code (--prune-core --optimize speed)
+	Release,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, clap::ValueEnum)]
+pub enum Optimize {
+	/// unroll function prologues only while that is smaller
+	Size,
+	/// unroll function prologues of up to 8 locals
+	Speed,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, clap::ValueEnum)]
+pub enum Emit {
+	/// call graph annotated with instruction counts, in graphviz DOT format (.dot)
+	Callgraph,
 }
 
 #[derive(Debug)]
 pub struct CliArgs {
-	pub input: Vec<PathBuf>,
+	pub input: Vec<VmSource>,
 	pub output: String,
+	pub emit: Vec<Emit>,
+	pub audit: bool,
+	pub parser_options: ParserOptions,
+	pub coder_options: CoderOptions,
+}
+
+const FILE_MARKER: &'static str = "//// FILE:";
+const STDIN_FILE_NAME: &'static str = "Stdin.vm";
+
+/// Split a single stream of vm code into virtual files. Each `//// FILE: Name.vm` marker line
+/// starts a new file; any code before the first marker belongs to a file named `Stdin.vm`.
+/// Marker names must be a plain `Name.vm` file name, as the name prefixes the file's statics.
+fn split_virtual_files(stream: &str) -> Result<Vec<VmSource>, InputError> {
+	let mut sources = vec![];
+	let mut path = PathBuf::from(STDIN_FILE_NAME);
+	let mut code = String::new();
+	for (i, line) in stream.lines().enumerate() {
+		if let Some(name) = line.trim().strip_prefix(FILE_MARKER) {
+			if !is_vm_file_name(name.trim()) {
+				return Err(InputError::InvalidFileMarker{name: name.trim().to_string(), line_num: i as u32 + 1});
+			}
+			if !code.trim().is_empty() {
+				sources.push(VmSource::Virtual{path, code});
+			}
+			path = PathBuf::from(name.trim());
+			code = String::new();
+			continue;
+		}
+		code.push_str(line);
+		code.push('\n');
+	}
+	if !code.trim().is_empty() {
+		sources.push(VmSource::Virtual{path, code});
+	}
+	Ok(sources)
 }
 
+fn is_vm_file_name(name: &str) -> bool {
+	let path = Path::new(name);
+	path.components().count() == 1
+		&& path.extension().is_some_and(|ext| ext == "vm")
+		&& path.file_stem().is_some_and(|stem| !stem.is_empty())
+}
+
+#[derive(Debug)]
 enum InputError {
 	NotFileOrDir(PathBuf),
+	InvalidFileMarker{name: String, line_num: u32},
 	IoError(std::io::Error),
 }
 
@@ -63,25 +160,98 @@ fn gather_input_files(input: Vec<PathBuf>) -> Result<Vec<PathBuf>, InputError> {
 	Ok(in_files)
 }
 
-pub fn parse_args() -> CliArgs {
-	let args = ClapArgs::parse();
-
-	let mut in_files = match gather_input_files(args.input){
-		Ok(files) => files,
-		Err(InputError::NotFileOrDir(e)) => {
-			println!("error: cannot find file or directory at path '{}'", e.to_string_lossy());
-			std::process::exit(0);
+fn exit_with_input_error(e: InputError) -> ! {
+	match e {
+		InputError::NotFileOrDir(path) => {
+			println!("error: cannot find file or directory at path '{}'", path.to_string_lossy());
+		},
+		InputError::InvalidFileMarker{name, line_num} => {
+			println!("error: invalid file marker '{}' on line {}; expected '{} Name.vm'", name, line_num, FILE_MARKER);
 		},
-		Err(InputError::IoError(e)) => {
+		InputError::IoError(e) => {
 			println!("error: invalid input! {}", e);
+		},
+	}
+	std::process::exit(0);
+}
+
+fn read_input(input: Vec<PathBuf>, stdin: bool) -> Vec<VmSource> {
+	if stdin {
+		let mut stream = String::new();
+		if let Err(e) = std::io::stdin().read_to_string(&mut stream) {
+			println!("error: failed to read stdin! {}", e);
 			std::process::exit(0);
+		}
+		return split_virtual_files(&stream).unwrap_or_else(|e| exit_with_input_error(e));
+	}
+
+	let mut in_files = gather_input_files(input).unwrap_or_else(|e| exit_with_input_error(e));
+
+	in_files.retain(|f| f.extension().is_some_and(|ext| ext == "vm"));
+
+	in_files.into_iter().map(VmSource::File).collect()
+}
+
+pub fn parse_args() -> CliArgs {
+	let args = ClapArgs::parse();
+
+	let instrument = args.instrument || args.profile == Some(Profile::Debug);
+	if instrument && args.instrument_base.is_none() {
+		ClapArgs::command().error(clap::error::ErrorKind::MissingRequiredArgument,
+			"instrumenting requires --instrument-base <ADDRESS>, RAM the program never uses").exit();
+	}
+	let coder_options = CoderOptions{
+		instrument_base: if instrument { args.instrument_base } else { None },
+		no_module_init: args.no_module_init,
+		prune_core: args.prune_core || args.profile == Some(Profile::Release),
+		templates: match (args.optimize, args.profile) {
+			(Some(Optimize::Speed), _) | (None, Some(Profile::Release)) => TemplateSet::Speed,
+			_ => TemplateSet::Size,
 		},
 	};
 
-	in_files = in_files.into_iter().filter(|f| {
-		let ext = f.extension();
-		!ext.is_none() && ext.unwrap() == "vm"
-	}).collect();
+	CliArgs{input: read_input(args.input, args.stdin), output: args.output, emit: args.emit, audit: args.audit,
+		parser_options: ParserOptions{strict: args.strict, extensions: args.ext,
+			allow_pop_constant: args.allow_pop_constant}, coder_options}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_split_virtual_files(){
+		let stream = "\
+			push constant 1
+			//// FILE: Class1.vm
+			function Class1.get 0
+			push static 0
+			return
+			//// FILE: Empty.vm
+			//// FILE:  Class2.vm  
+			function Class2.get 0
+		";
+
+		let sources = split_virtual_files(stream).unwrap();
+		assert_eq!(sources.len(), 3);
+
+		// Code before the first marker should be collected into a default file.
+		assert_eq!(sources[0].path(), &PathBuf::from("Stdin.vm"));
 
-	CliArgs{input: in_files, output: args.output}
+		// Markers should delimit files and be excluded from their code.
+		assert_eq!(sources[1].path(), &PathBuf::from("Class1.vm"));
+		match &sources[1] {
+			VmSource::Virtual{code, ..} => assert_eq!(code.lines().count(), 3),
+			_ => panic!("expected virtual file"),
+		}
+
+		// Files with no code should be dropped; marker names should be trimmed.
+		assert_eq!(sources[2].path(), &PathBuf::from("Class2.vm"));
+
+		// Markers without a plain Name.vm file name should be rejected.
+		for marker in ["//// FILE:", "//// FILE: ..", "//// FILE: .vm", "//// FILE: Main", "//// FILE: dir/Main.vm"] {
+			let stream = format!("push constant 1\n{}\npush constant 2\n", marker);
+			assert!(matches!(split_virtual_files(&stream), Err(InputError::InvalidFileMarker{line_num: 2, ..})), "{}", marker);
+		}
+	}
 }
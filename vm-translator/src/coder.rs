@@ -5,7 +5,7 @@ use crate::parser::*;
 use crate::errors::*;
 
 const CALL_STACK_BASE_ADDRESS: u16 = 256;
-const TEMP_SEGMENT_BASE_ADDRESS: u16 = 5;
+const SCREEN_BASE_ADDRESS: u16 = 16384;
 const MAX_STATIC_VARIABLES: usize = 240;
 
 const EQ_IMPL_LABEL: &'static str = "__EQ_IMPL";
@@ -13,15 +13,121 @@ const GT_IMPL_LABEL: &'static str = "__GT_IMPL";
 const LT_IMPL_LABEL: &'static str = "__LT_IMPL";
 const RETURN_IMPL_LABEL: &'static str = "__RETURN_IMPL";
 const CALL_IMPL_LABEL: &'static str = "__CALL_IMPL";
-const ENTRY_IMPL_LABEL: &'static str = "__ENTRY_IMPL";
+const DISPATCH_IMPL_LABEL: &'static str = "__DISPATCH_IMPL";
+const DISPATCH_TABLE_LABEL: &'static str = "__DISPATCH_TABLE";
+const HANG_LABEL: &'static str = "__HANG";
+const MODULE_INIT_IMPL_LABEL: &'static str = "__MODULE_INIT_IMPL";
+const MODULE_INIT_SUFFIX: &'static str = ".init__static";
+
+/// Instruction templates specialised for particular indices, and the limit on unrolling
+/// function prologues; any other instruction uses the general template. Templates are
+/// assembly, where `{seg}` stands for the base register of the segment and `{comp}` for the
+/// computation of an inc or dec.
+pub struct Templates {
+	/// Functions with at most this many locals initialize each in turn, others in a loop.
+	pub max_unrolled_locals: u16,
+	/// `push constant i` by i.
+	pub push_constant: &'static [(u16, &'static str)],
+	/// `push` of the local, argument, this and that segments by index.
+	pub push_indirect: &'static [(u16, &'static str)],
+	/// `pop` of the local, argument, this and that segments by index.
+	pub pop_indirect: &'static [(u16, &'static str)],
+	/// `inc` and `dec` of the local, argument, this and that segments by index.
+	pub step_indirect: &'static [(u16, &'static str)],
+}
+
+const PUSH_CONSTANT_TEMPLATES: &[(u16, &'static str)] = &[
+	(0, "@SP\nM=M+1\nA=M-1\nM=0\n"),
+	(1, "@SP\nM=M+1\nA=M-1\nM=1\n"),
+];
+
+const PUSH_INDIRECT_TEMPLATES: &[(u16, &'static str)] = &[
+	(0, "@{seg}\nA=M\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n"),
+	(1, "@{seg}\nA=M+1\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n"),
+];
+
+const POP_INDIRECT_TEMPLATES: &[(u16, &'static str)] = &[
+	(0, "@SP\nM=M-1\nA=M\nD=M\n@{seg}\nD=D+M\n@SP\nA=M\nA=M\nA=D-A\nM=D-A\n"),
+	(1, "@SP\nM=M-1\nA=M\nD=M+1\n@{seg}\nD=D+M\n@SP\nA=M\nA=M\nA=D-A\nM=D-A\n"),
+];
+
+const STEP_INDIRECT_TEMPLATES: &[(u16, &'static str)] = &[
+	(0, "@{seg}\nA=M\nM={comp}\n"),
+	(1, "@{seg}\nA=M+1\nM={comp}\n"),
+];
+
+/// Each local initialized in turn takes 4 instructions, the loop 9; so unroll only while smaller.
+pub const SIZE_TEMPLATES: Templates = Templates{
+	max_unrolled_locals: 2,
+	push_constant: PUSH_CONSTANT_TEMPLATES,
+	push_indirect: PUSH_INDIRECT_TEMPLATES,
+	pop_indirect: POP_INDIRECT_TEMPLATES,
+	step_indirect: STEP_INDIRECT_TEMPLATES,
+};
+
+/// Each local initialized in turn takes 4 cycles, in the loop 7; so unroll within reason.
+pub const SPEED_TEMPLATES: Templates = Templates{
+	max_unrolled_locals: 8,
+	..SIZE_TEMPLATES
+};
+
+/// Which [`Templates`] the [`Coder`] uses.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum TemplateSet {
+	#[default]
+	Size,
+	Speed,
+}
+
+impl TemplateSet {
+	pub fn templates(self) -> &'static Templates {
+		match self {
+			TemplateSet::Size => &SIZE_TEMPLATES,
+			TemplateSet::Speed => &SPEED_TEMPLATES,
+		}
+	}
+}
+
+/// The label of a function. Qualified names, `File.name`, are global so are labels as they
+/// are; any other name is qualified by the file which declares or calls it.
+pub fn function_label(file: &str, name: &str) -> CompactString {
+	match name.contains('.') {
+		true => CompactString::from(name),
+		false => format!("{}.{}", file, name).into(),
+	}
+}
+
+/// Options which change the code generated by the [`Coder`].
+#[derive(Debug, Default, Clone)]
+pub struct CoderOptions {
+	/// When set, each function increments its own RAM counter on entry; counters are
+	/// allocated sequentially from this address in order of function definition.
+	pub instrument_base: Option<u16>,
+	/// When set, the bootstrap does not call module initializers; functions named
+	/// `File.init__static`, called in order of definition before Sys.init.
+	pub no_module_init: bool,
+	/// When set, core subroutines are written after all functions, and only those which the
+	/// translated code uses; e.g. the comparisons are omitted from arithmetic-only programs.
+	pub prune_core: bool,
+	/// Templates of the instructions which have specialised forms.
+	pub templates: TemplateSet,
+}
 
 pub struct Coder {
-	call_count: usize,
-	eq_count: usize,
-	lt_count: usize,
-	gt_count: usize,
+	options: CoderOptions,
+	scope: CompactString,
+	label_count: usize,
+	has_eq: bool,
+	has_lt: bool,
+	has_gt: bool,
+	counters: Vec<(u16, CompactString)>,
+	functions: Vec<CompactString>,
+	module_inits: Vec<CompactString>,
+	has_indirect_calls: bool,
+	has_returns: bool,
 }
 
+#[derive(Default)]
 pub struct InsContext {
 	pub vm_file_name: CompactString,
 	pub vm_function_name: CompactString,
@@ -34,21 +140,131 @@ impl InsContext {
 }
 
 impl Coder {
-	pub fn new() -> Self {
-		Coder{call_count: 0, eq_count: 0, lt_count: 0, gt_count: 0}
+	pub fn new(options: CoderOptions) -> Self {
+		Coder{options, scope: CompactString::new(""), label_count: 0, has_eq: false, has_lt: false, has_gt: false,
+			counters: vec![], functions: vec![],
+			module_inits: vec![], has_indirect_calls: false, has_returns: false}
+	}
+
+	/// A new label scoped to the current function, `Function$__kind.n`, numbered from 1 in each
+	/// function; so labels of one function do not depend on the code of any other. The reserved
+	/// prefix keeps generated labels apart from the labels of the vm code.
+	fn next_label(&mut self, ctx: &InsContext, kind: &str) -> CompactString {
+		let scope = function_label(&ctx.vm_file_name, &ctx.vm_function_name);
+		if scope != self.scope {
+			self.scope = scope;
+			self.label_count = 0;
+		}
+		self.label_count += 1;
+		format!("{}${}{}.{}", self.scope, RESERVED_LABEL_PREFIX, kind, self.label_count).into()
+	}
+
+	/// The RAM address of each instrumentation counter and the function which increments it.
+	pub fn get_counters(&self) -> &[(u16, CompactString)] {
+		&self.counters
+	}
+
+	fn write_counter_ins<W: Write>(&mut self, out: &mut W, label: &CompactString) -> Result<(), CodeError> {
+		let base = match self.options.instrument_base {
+			Some(base) => base,
+			None => return Ok(()),
+		};
+		let address = base as usize + self.counters.len();
+		if address >= SCREEN_BASE_ADDRESS as usize {
+			return Err(CodeError::CounterOverflow{function: label.clone(), address});
+		}
+		self.counters.push((address as u16, label.clone()));
+		write!(out, "\
+			@{}\n\
+			M=M+1\n\
+		", address)?;
+		Ok(())
+	}
+
+	/// Write the dispatch table of `call-indirect`, once every function has been written. A
+	/// function's id is its index in order of definition; calls to an unknown id halt.
+	pub fn write_dispatch_impl<W: Write>(&mut self, out: &mut W) -> Result<(), CodeError> {
+		if !self.has_indirect_calls {
+			return Ok(());
+		}
+		write!(out, "\
+			({})\n\
+			@R15\n\
+			D=M\n\
+			@{}\n\
+			D;JLT\n\
+			@{}\n\
+			D=D-A\n\
+			@{}\n\
+			D;JGE\n\
+			@R15\n\
+			D=M\n\
+			D=D+M\n\
+			@{}\n\
+			A=D+A\n\
+			0;JMP\n\
+			({})\n\
+		", DISPATCH_IMPL_LABEL, HANG_LABEL, self.functions.len(), HANG_LABEL, DISPATCH_TABLE_LABEL, DISPATCH_TABLE_LABEL)?;
+		for function in &self.functions {
+			write!(out, "\
+				@{}\n\
+				0;JMP\n\
+			", function)?;
+		}
+		Ok(())
+	}
+
+	/// Write the module initializer calls made by the bootstrap, once every function has
+	/// been written. Initializers take no arguments and their return values are discarded.
+	pub fn write_module_init_impl<W: Write>(&mut self, out: &mut W) -> Result<(), CodeError> {
+		if self.options.no_module_init {
+			return Ok(());
+		}
+		write!(out, "({})\n", MODULE_INIT_IMPL_LABEL)?;
+		for (i, function) in self.module_inits.iter().enumerate() {
+			write!(out, "\
+				@R13\n\
+				M=0\n\
+				@{}\n\
+				D=A\n\
+				@R14\n\
+				M=D\n\
+				@__RET_MODULE_INIT{}\n\
+				D=A\n\
+				@{}\n\
+				0;JMP\n\
+				(__RET_MODULE_INIT{})\n\
+				@SP\n\
+				M=M-1\n\
+			", function, i, CALL_IMPL_LABEL, i)?;
+		}
+		write!(out, "\
+			@__RET_MODULE_INIT\n\
+			0;JMP\n\
+		")?;
+		Ok(())
 	}
 
 	pub fn write_core_impl<W: Write>(&mut self, out: &mut W) -> Result<(), CodeError> {
+		let module_init = match self.options.no_module_init {
+			true => String::new(),
+			false => format!("\
+				@{}\n\
+				0;JMP\n\
+				(__RET_MODULE_INIT)\n\
+			", MODULE_INIT_IMPL_LABEL),
+		};
 		let bootstrap_impl = format!("\
 			@{}\n\
 			D=A\n\
 			@SP\n\
 			M=D\n\
+			{}\
 			@0\n\
 			D=A\n\
 			@R13\n\
 			M=D\n\
-			@sys.init\n\
+			@Sys.init\n\
 			D=A\n\
 			@R14\n\
 			M=D\n\
@@ -57,10 +273,32 @@ impl Coder {
 			@{}\n\
 			0;JMP\n\
 			(__RET_SYS_INIT)\n\
-			(__HANG)\n\
-			@__HANG\n\
+			({})\n\
+			@{}\n\
 			0;JMP\n\
-		", CALL_STACK_BASE_ADDRESS, CALL_IMPL_LABEL);
+		", CALL_STACK_BASE_ADDRESS, module_init, CALL_IMPL_LABEL, HANG_LABEL, HANG_LABEL);
+
+		write!(out, "{}", bootstrap_impl)?;
+		if !self.options.prune_core {
+			self.write_core_subroutines(out, false)?;
+		}
+
+		Ok(())
+	}
+
+	/// Write the core subroutines used by the translated code, once every function has been
+	/// written. Only when pruning; otherwise every subroutine is written with the bootstrap.
+	pub fn write_pruned_core_impl<W: Write>(&mut self, out: &mut W) -> Result<(), CodeError> {
+		if !self.options.prune_core {
+			return Ok(());
+		}
+		self.write_core_subroutines(out, true)
+	}
+
+	/// Write the subroutines shared by translated instructions; when `used_only`, omit those
+	/// not used by the instructions written so far. The call subroutine is always used by the
+	/// bootstrap.
+	fn write_core_subroutines<W: Write>(&self, out: &mut W, used_only: bool) -> Result<(), CodeError> {
 		let eq_impl = format!("\
 			({})\n\
 			@R15\n\
@@ -206,139 +444,164 @@ impl Coder {
 			A=M\n\
 			0;JMP\n\
 		", CALL_IMPL_LABEL);
-	
-		write!(out, "{}", bootstrap_impl)?;
-		write!(out, "{}", eq_impl)?;
-		write!(out, "{}", gt_impl)?;
-		write!(out, "{}", lt_impl)?;
-		write!(out, "{}", return_impl)?;
-		write!(out, "{}", call_impl)?;
-	
+
+		let subroutines = [
+			(eq_impl, self.has_eq),
+			(gt_impl, self.has_gt),
+			(lt_impl, self.has_lt),
+			(return_impl, self.has_returns),
+			(call_impl, true),
+		];
+		for (subroutine, used) in subroutines {
+			if used || !used_only {
+				write!(out, "{}", subroutine)?;
+			}
+		}
+
 		Ok(())
 	}
 
 	pub fn write_vm_ins<W: Write>(&mut self, out: &mut W, vm_ins: VmIns, ctx: &InsContext) -> Result<(), CodeError> {
+		let templates = self.options.templates.templates();
 		return match vm_ins {
-			VmIns::Function{name, locals_count} => write_function_ins(out, ctx, name, locals_count),
-			VmIns::Call{function, args_count} => {self.call_count += 1; write_call_ins(out, ctx, function, args_count, self.call_count)},
-			VmIns::Push{segment, index} => write_push_ins(out, ctx, segment, index),
-			VmIns::Pop{segment, index} => write_pop_ins(out, ctx, segment, index),
+			VmIns::Function{name, locals_count} => {
+				let label = function_label(&ctx.vm_file_name, &name);
+				if name.ends_with(MODULE_INIT_SUFFIX) {
+					self.module_inits.push(label.clone());
+				}
+				self.functions.push(label.clone());
+				write_function_ins(out, ctx, templates, name.clone(), locals_count)?;
+				self.write_counter_ins(out, &label)
+			},
+			VmIns::Call{function, args_count} => {
+				let return_label = self.next_label(ctx, "ret");
+				write_call_ins(out, ctx, function, args_count, &return_label)
+			},
+			VmIns::CallIndirect{args_count} => {
+				self.has_indirect_calls = true;
+				let return_label = self.next_label(ctx, "ret");
+				write_call_indirect_ins(out, args_count, &return_label)
+			},
+			VmIns::Push{segment, index} => write_push_ins(out, ctx, templates, segment, index),
+			VmIns::Pop{segment, index} => write_pop_ins(out, ctx, templates, segment, index),
+			VmIns::Inc{segment, index} => write_step_ins(out, ctx, templates, segment, index, "M+1"),
+			VmIns::Dec{segment, index} => write_step_ins(out, ctx, templates, segment, index, "M-1"),
 			VmIns::Label{label} => write_label_ins(out, ctx, label),
 			VmIns::IfGoto{label} => write_if_goto_ins(out, ctx, label),
 			VmIns::Goto{label} => write_goto_ins(out, ctx, label),
-			VmIns::Return => write_return_ins(out),
+			VmIns::Return => {self.has_returns = true; write_return_ins(out)},
 			VmIns::Add => write_add_ins(out),
 			VmIns::Sub => write_sub_ins(out),
 			VmIns::Neg => write_neg_ins(out),
 			VmIns::And => write_and_ins(out),
 			VmIns::Or => write_or_ins(out),
 			VmIns::Not => write_not_ins(out),
-			VmIns::Eq => {self.eq_count += 1; write_eq_ins(out, self.eq_count)},
-			VmIns::Lt => {self.lt_count += 1; write_lt_ins(out, self.lt_count)},
-			VmIns::Gt => {self.gt_count += 1; write_gt_ins(out, self.gt_count)},
+			VmIns::Eq => {self.has_eq = true; write_compare_ins(out, EQ_IMPL_LABEL, &self.next_label(ctx, "eq"))},
+			VmIns::Lt => {self.has_lt = true; write_compare_ins(out, LT_IMPL_LABEL, &self.next_label(ctx, "lt"))},
+			VmIns::Gt => {self.has_gt = true; write_compare_ins(out, GT_IMPL_LABEL, &self.next_label(ctx, "gt"))},
 		};
 	
-		fn write_function_ins<W: Write>(out: &mut W, ctx: &InsContext, name: CompactString, locals_count: u16) -> Result<(), CodeError> {
+		fn write_template<W: Write>(out: &mut W, template: &str, seg: &str, comp: &str) -> Result<(), CodeError> {
+			out.write_all(template.replace("{seg}", seg).replace("{comp}", comp).as_bytes())?;
+			Ok(())
+		}
+
+		fn find_template(table: &[(u16, &'static str)], index: u16) -> Option<&'static str> {
+			table.iter().find(|(i, _)| *i == index).map(|(_, template)| *template)
+		}
+
+		fn write_function_ins<W: Write>(out: &mut W, ctx: &InsContext, templates: &Templates, name: CompactString, locals_count: u16) -> Result<(), CodeError> {
 			debug_assert_eq!(name, ctx.vm_function_name);
-			match locals_count {
-				0 => {
+			if locals_count <= templates.max_unrolled_locals {
+				write!(out, "({})\n", function_label(&ctx.vm_file_name, &name))?;
+				for _ in 0..locals_count {
 					write!(out, "\
-						({}.{})\n\
-					", ctx.vm_file_name, name)?;
-				},
-				1 => {
-					write!(out, "\
-						({}.{})\n\
 						@SP\n\
 						AM=M+1\n\
 						A=A-1\n\
 						M=0\n\
-					", ctx.vm_file_name, name)?;
-				},
-				2 => {
-					write!(out, "\
-						({}.{})\n\
-						@SP\n\
-						AM=M+1\n\
-						A=A-1\n\
-						M=0\n\
-						@SP\n\
-						AM=M+1\n\
-						A=A-1\n\
-						M=0\n\
-					", ctx.vm_file_name, name)?;
-				},
-				_ => {
-					write!(out, "\
-						({}.{})\n\
-						@{}\n\
-						D=A\n\
-						(__LOOP_{}.{})\n\
-						D=D-1\n\
-						@SP\n\
-						AM=M+1\n\
-						A=A-1\n\
-						M=0\n\
-						@__LOOP_{}.{}\n\
-						D;JGT\n\
-					", ctx.vm_file_name, name, locals_count, ctx.vm_file_name, name, ctx.vm_file_name, name)?;
-				},
-			};
+					")?;
+				}
+				return Ok(());
+			}
+			let label = function_label(&ctx.vm_file_name, &name);
+			write!(out, "\
+				({})\n\
+				@{}\n\
+				D=A\n\
+				(__LOOP_{})\n\
+				D=D-1\n\
+				@SP\n\
+				AM=M+1\n\
+				A=A-1\n\
+				M=0\n\
+				@__LOOP_{}\n\
+				D;JGT\n\
+			", label, locals_count, label, label)?;
 			Ok(())
 		}
 	
-		fn write_call_ins<W: Write>(out: &mut W, ctx: &InsContext, function: CompactString, args_count: u16, call_count: usize) -> Result<(), CodeError> {
+		fn write_call_ins<W: Write>(out: &mut W, ctx: &InsContext, function: CompactString, args_count: u16, return_label: &str) -> Result<(), CodeError> {
+			let label = function_label(&ctx.vm_file_name, &function);
 			write!(out, "\
 				@{}\n\
 				D=A\n\
 				@R13\n\
 				M=D\n\
-				@{}.{}\n\
+				@{}\n\
 				D=A\n\
 				@R14 \n\
 				M=D\n\
-				@{}.{}$ret.{}\n\
+				@{}\n\
 				D=A\n\
 				@{}\n\
 				0;JMP\n\
-			", args_count, ctx.vm_file_name, function, ctx.vm_file_name, function, call_count, CALL_IMPL_LABEL)?;
+				({})\n\
+			", args_count, label, return_label, CALL_IMPL_LABEL, return_label)?;
 			Ok(())
 		}
 	
-		fn write_push_ins<W: Write>(out: &mut W, ctx: &InsContext, segment: VmSeg, index: u16) -> Result<(), CodeError> {
+		fn write_call_indirect_ins<W: Write>(out: &mut W, args_count: u16, return_label: &str) -> Result<(), CodeError> {
+			write!(out, "\
+				@SP\n\
+				AM=M-1\n\
+				D=M\n\
+				@R15\n\
+				M=D\n\
+				@{}\n\
+				D=A\n\
+				@R13\n\
+				M=D\n\
+				@{}\n\
+				D=A\n\
+				@R14\n\
+				M=D\n\
+				@{}\n\
+				D=A\n\
+				@{}\n\
+				0;JMP\n\
+				({})\n\
+			", args_count, DISPATCH_IMPL_LABEL, return_label, CALL_IMPL_LABEL, return_label)?;
+			Ok(())
+		}
+
+		fn write_push_ins<W: Write>(out: &mut W, ctx: &InsContext, templates: &Templates, segment: VmSeg, index: u16) -> Result<(), CodeError> {
 			let label = compose_segment_label(ctx, segment, index)?;
 			match segment {
 				VmSeg::Constant => {
-					match index {
-						0 => {
-							write!(out, "\
-								@SP\n\
-								M=M+1\n\
-								A=M-1\n\
-								M=0\n\
-							")?;
-						},
-						1 => {
-							write!(out, "\
-								@SP\n\
-								M=M+1\n\
-								A=M-1\n\
-								M=1\n\
-							")?;
-						},
-						_ => { 
-							write!(out, "\
-								@{}\n\
-								D=A\n\
-								@SP\n\
-								M=M+1\n\
-								A=M-1\n\
-								M=D\n\
-							", index)?;
-						},
+					if let Some(template) = find_template(templates.push_constant, index) {
+						return write_template(out, template, "", "");
 					}
+					write!(out, "\
+						@{}\n\
+						D=A\n\
+						@SP\n\
+						M=M+1\n\
+						A=M-1\n\
+						M=D\n\
+					", index)?;
 				},
-				VmSeg::Static => {
+				VmSeg::Static | VmSeg::Temp | VmSeg::Pointer => {
 					write!(out, "\
 						@{}\n\
 						D=M\n\
@@ -349,53 +612,35 @@ impl Coder {
 					", label)?;
 				},
 				_ => {
-					match index {
-						0 => {
-							write!(out, "\
-								@{}\n\
-								A=M\n\
-								D=M\n\
-								@SP\n\
-								AM=M+1\n\
-								A=A-1\n\
-								M=D\n\
-							", label)?;
-						},
-						1 => {
-							write!(out, "\
-								@{}\n\
-								A=M+1\n\
-								D=M\n\
-								@SP\n\
-								AM=M+1\n\
-								A=A-1\n\
-								M=D\n\
-							", label)?;
-						},
-						_ => { 
-							write!(out, "\
-								@{}\n\
-								D=A\n\
-								@{}\n\
-								A=M+D\n\
-								D=M\n\
-								@SP\n\
-								AM=M+1\n\
-								A=A-1\n\
-								M=D\n\
-							", index, label)?;
-						},
-					};
+					if let Some(template) = find_template(templates.push_indirect, index) {
+						return write_template(out, template, &label, "");
+					}
+					write!(out, "\
+						@{}\n\
+						D=A\n\
+						@{}\n\
+						A=M+D\n\
+						D=M\n\
+						@SP\n\
+						AM=M+1\n\
+						A=A-1\n\
+						M=D\n\
+					", index, label)?;
 				}
 			};
 			Ok(())
 		}
 	
-		fn write_pop_ins<W: Write>(out: &mut W, ctx: &InsContext, segment: VmSeg, index: u16) -> Result<(), CodeError> {
+		fn write_pop_ins<W: Write>(out: &mut W, ctx: &InsContext, templates: &Templates, segment: VmSeg, index: u16) -> Result<(), CodeError> {
 			let label = compose_segment_label(ctx, segment, index)?;
 			match segment {
-				VmSeg::Constant => (), // NOP
-				VmSeg::Static => {
+				VmSeg::Constant => {
+					write!(out, "\
+						@SP\n\
+						M=M-1\n\
+					")?;
+				},
+				VmSeg::Static | VmSeg::Temp | VmSeg::Pointer => {
 					write!(out, "\
 						@SP\n\
 						M=M-1\n\
@@ -406,64 +651,60 @@ impl Coder {
 					", label)?;
 				},
 				_ => {
-					match index {
-						0 => {
-							write!(out, "\
-								@SP\n\
-								M=M-1\n\
-								A=M\n\
-								D=M\n\
-								@{}\n\
-								D=D+M\n\
-								@SP\n\
-								A=M\n\
-								A=M\n\
-								A=D-A\n\
-								M=D-A\n\
-							", label)?;
-						},
-						1 => {
-							write!(out, "\
-								@SP\n\
-								M=M-1\n\
-								A=M\n\
-								D=M+1\n\
-								@{}\n\
-								D=D+M\n\
-								@SP\n\
-								A=M\n\
-								A=M\n\
-								A=D-A\n\
-								M=D-A\n\
-							", label)?;
-						},
-						_ => { 
-							write!(out, "\
-								@SP\n\
-								M=M-1\n\
-								A=M\n\
-								D=M+1\n\
-								@{}\n\
-								D=D+M\n\
-								@{}\n\
-								D=D+A\n\
-								@SP\n\
-								A=M\n\
-								A=M\n\
-								A=D-A\n\
-								M=D-A\n\
-							", index, label)?;
-						},
+					if let Some(template) = find_template(templates.pop_indirect, index) {
+						return write_template(out, template, &label, "");
 					}
+					write!(out, "\
+						@SP\n\
+						M=M-1\n\
+						A=M\n\
+						D=M\n\
+						@{}\n\
+						D=D+M\n\
+						@{}\n\
+						D=D+A\n\
+						@SP\n\
+						A=M\n\
+						A=M\n\
+						A=D-A\n\
+						M=D-A\n\
+					", label, index)?;
 				},
 			};
 			Ok(())
 		}
 	
+		/// Increment or decrement a segment in place, rather than as push, push, add, pop.
+		fn write_step_ins<W: Write>(out: &mut W, ctx: &InsContext, templates: &Templates, segment: VmSeg, index: u16, comp: &str) -> Result<(), CodeError> {
+			debug_assert_ne!(segment, VmSeg::Constant);
+			let label = compose_segment_label(ctx, segment, index)?;
+			match segment {
+				VmSeg::Static | VmSeg::Temp | VmSeg::Pointer => {
+					write!(out, "\
+						@{}\n\
+						M={}\n\
+					", label, comp)?;
+				},
+				_ => {
+					if let Some(template) = find_template(templates.step_indirect, index) {
+						return write_template(out, template, &label, comp);
+					}
+					write!(out, "\
+						@{}\n\
+						D=A\n\
+						@{}\n\
+						A=D+M\n\
+						M={}\n\
+					", index, label, comp)?;
+				},
+			}
+			Ok(())
+		}
+
 		fn write_label_ins<W: Write>(out: &mut W, ctx: &InsContext, label: CompactString) -> Result<(), CodeError> {
 			write!(out, "\
-				({}.{}${})\n\
-			", ctx.vm_file_name, ctx.vm_function_name, label)?;
+				({}${})\n\
+			", function_label(&ctx.vm_file_name, &ctx.vm_function_name), label)?;
 			Ok(())
 		}
 	
@@ -472,17 +713,17 @@ impl Coder {
 				@SP\n\
 				AM=M-1\n\
 				D=M\n\
-				@{}.{}${}\n\
+				@{}${}\n\
 				D;JNE\n\
-			", ctx.vm_file_name, ctx.vm_function_name, label)?;
+			", function_label(&ctx.vm_file_name, &ctx.vm_function_name), label)?;
 			Ok(())
 		}
 	
 		fn write_goto_ins<W: Write>(out: &mut W, ctx: &InsContext, label: CompactString) -> Result<(), CodeError> {
 			write!(out, "\
-				@{}.{}${}\n\
+				@{}${}\n\
 				0;JMP\n\
-			", ctx.vm_file_name, ctx.vm_function_name, label)?;
+			", function_label(&ctx.vm_file_name, &ctx.vm_function_name), label)?;
 			Ok(())
 		}
 	
@@ -556,36 +797,15 @@ impl Coder {
 			Ok(())
 		}
 	
-		fn write_eq_ins<W: Write>(out: &mut W, count: usize) -> Result<(), CodeError> {
+		/// Call a comparison subroutine; each returns to the label after its call.
+		fn write_compare_ins<W: Write>(out: &mut W, impl_label: &str, return_label: &str) -> Result<(), CodeError> {
 			write!(out, "\
-				@__RET_EQ{}\n\
-				D=A\n\
 				@{}\n\
-				0;JMP\n\
-				(__RET_EQ{})\n\
-			", count, EQ_IMPL_LABEL, count)?;
-			Ok(())
-		}
-	
-		fn write_lt_ins<W: Write>(out: &mut W, count: usize) -> Result<(), CodeError> {
-			write!(out, "\
-				@__RET_LT{}\n\
 				D=A\n\
 				@{}\n\
 				0;JMP\n\
-				(__RET_LT{})\n\
-			", count, LT_IMPL_LABEL, count)?;
-			Ok(())
-		}
-	
-		fn write_gt_ins<W: Write>(out: &mut W, count: usize) -> Result<(), CodeError> {
-			write!(out, "\
-				@__RET_GT{}\n\
-				D=A\n\
-				@{}\n\
-				0;JMP\n\
-				(__RET_GT{})\n\
-			", count, GT_IMPL_LABEL, count)?;
+				({})\n\
+			", return_label, impl_label, return_label)?;
 			Ok(())
 		}
 
@@ -616,27 +836,221 @@ impl Coder {
 					if index as usize >= MAX_STATIC_VARIABLES {
 						return Err(CodeError::IndexOutOfBounds{segment: VmSeg::Static, index, bounds: 0..(MAX_STATIC_VARIABLES - 1)});
 					}
-					let mut label = ctx.vm_file_name.clone();
-					label.push('.');
-					let mut buf = ['\0'; 3];
-					let mut i = 2;
-					let mut num = index;
-					while num > 0 {
-						debug_assert!(i > 0);
-						let digit = (num % 10) as u8;
-						buf[i] = char::from_digit(digit.into(), 10).unwrap();
-						num /= 10;
-						i -= 1;
-					}
-					for c in buf {
-						if c == '\0' {
-							continue;
-						}
-						label.push(c);
-					}
-					Ok(label)
+					Ok(format!("{}.{}", ctx.vm_file_name, index).into())
 				},
 			}
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn function_ins(name: &str) -> (VmIns, InsContext) {
+		let mut ctx = InsContext::new();
+		ctx.vm_file_name = CompactString::from("Foo");
+		ctx.vm_function_name = CompactString::from(name);
+		(VmIns::Function{name: CompactString::from(name), locals_count: 0}, ctx)
+	}
+
+	#[test]
+	fn test_instrumentation_counters(){
+		let mut coder = Coder::new(CoderOptions{instrument_base: Some(16382), ..CoderOptions::default()});
+		let mut out = vec![];
+
+		// Each function should increment its own counter on entry, keyed by its function label.
+		for name in ["Foo.a", "b"] {
+			let (ins, ctx) = function_ins(name);
+			coder.write_vm_ins(&mut out, ins, &ctx).unwrap();
+		}
+		assert_eq!(String::from_utf8(out).unwrap(), "(Foo.a)\n@16382\nM=M+1\n(Foo.b)\n@16383\nM=M+1\n");
+		assert_eq!(coder.get_counters(), &[(16382, CompactString::from("Foo.a")), (16383, CompactString::from("Foo.b"))]);
+
+		// Counters should not be allocated in the screen memory map.
+		let (ins, ctx) = function_ins("Foo.c");
+		assert!(matches!(coder.write_vm_ins(&mut vec![], ins, &ctx), Err(CodeError::CounterOverflow{address: 16384, ..})));
+	}
+
+	#[test]
+	fn test_dispatch_table(){
+		let mut coder = Coder::new(CoderOptions::default());
+
+		// Without indirect calls there is no need for a table.
+		let mut out = vec![];
+		coder.write_dispatch_impl(&mut out).unwrap();
+		assert!(out.is_empty());
+
+		let mut out = vec![];
+		for name in ["Foo.a", "Foo.b"] {
+			let (ins, ctx) = function_ins(name);
+			coder.write_vm_ins(&mut out, ins, &ctx).unwrap();
+		}
+		let (_, ctx) = function_ins("Foo.b");
+		coder.write_vm_ins(&mut out, VmIns::CallIndirect{args_count: 1}, &ctx).unwrap();
+
+		// Function ids should index the table in order of definition.
+		let mut out = vec![];
+		coder.write_dispatch_impl(&mut out).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		assert!(out.contains("@2\nD=D-A\n@__HANG\nD;JGE\n"));
+		assert!(out.ends_with("(__DISPATCH_TABLE)\n@Foo.a\n0;JMP\n@Foo.b\n0;JMP\n"));
+	}
+
+	#[test]
+	fn test_module_initializers(){
+		let mut coder = Coder::new(CoderOptions::default());
+		let mut out = vec![];
+		coder.write_core_impl(&mut out).unwrap();
+		assert!(String::from_utf8(out).unwrap().starts_with("@256\nD=A\n@SP\nM=D\n@__MODULE_INIT_IMPL\n0;JMP\n(__RET_MODULE_INIT)\n"));
+
+		for name in ["Foo.init__static", "Foo.a", "Foo.init"] {
+			let (ins, ctx) = function_ins(name);
+			coder.write_vm_ins(&mut vec![], ins, &ctx).unwrap();
+		}
+
+		// Only functions following the naming convention should be called.
+		let mut out = vec![];
+		coder.write_module_init_impl(&mut out).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		assert_eq!(out.matches("@__CALL_IMPL").count(), 1);
+		assert!(out.contains("@Foo.init__static\n"));
+		assert!(out.ends_with("@SP\nM=M-1\n@__RET_MODULE_INIT\n0;JMP\n"));
+
+		// Opting out should remove the calls from the bootstrap.
+		let mut coder = Coder::new(CoderOptions{no_module_init: true, ..CoderOptions::default()});
+		let (mut core, mut init) = (vec![], vec![]);
+		coder.write_core_impl(&mut core).unwrap();
+		coder.write_module_init_impl(&mut init).unwrap();
+		assert!(!String::from_utf8(core).unwrap().contains("MODULE_INIT"));
+		assert!(init.is_empty());
+	}
+
+	#[test]
+	fn test_pruned_core(){
+		let (_, ctx) = function_ins("Foo.a");
+		let core = |prune_core: bool, inss: Vec<VmIns>| {
+			let mut coder = Coder::new(CoderOptions{prune_core, ..CoderOptions::default()});
+			let mut out = vec![];
+			coder.write_core_impl(&mut out).unwrap();
+			for ins in inss {
+				coder.write_vm_ins(&mut vec![], ins, &ctx).unwrap();
+			}
+			coder.write_pruned_core_impl(&mut out).unwrap();
+			let out = String::from_utf8(out).unwrap();
+			[EQ_IMPL_LABEL, GT_IMPL_LABEL, LT_IMPL_LABEL, RETURN_IMPL_LABEL, CALL_IMPL_LABEL].iter()
+				.filter(|label| out.contains(&format!("({})", label)))
+				.count()
+		};
+
+		// Without pruning every subroutine should be written.
+		assert_eq!(core(false, vec![]), 5);
+
+		// Pruning should keep only the subroutines used; the bootstrap always calls Sys.init.
+		assert_eq!(core(true, vec![VmIns::Add, VmIns::Sub]), 1);
+		assert_eq!(core(true, vec![VmIns::Eq, VmIns::Lt, VmIns::Eq]), 3);
+		assert_eq!(core(true, vec![VmIns::Gt, VmIns::Return]), 3);
+	}
+
+	#[test]
+	fn test_label_numbering(){
+		let code = |names: &[&str]| {
+			let mut coder = Coder::new(CoderOptions::default());
+			let mut out = vec![];
+			for name in names {
+				let (ins, ctx) = function_ins(name);
+				coder.write_vm_ins(&mut out, ins, &ctx).unwrap();
+				for ins in [VmIns::Call{function: CompactString::from("Bar.c"), args_count: 0}, VmIns::Eq, VmIns::Lt, VmIns::CallIndirect{args_count: 0}] {
+					coder.write_vm_ins(&mut out, ins, &ctx).unwrap();
+				}
+			}
+			String::from_utf8(out).unwrap()
+		};
+
+		// Labels should be numbered within their function.
+		let out = code(&["Foo.a", "Foo.b"]);
+		for label in ["(Foo.a$__ret.1)", "(Foo.a$__eq.2)", "(Foo.a$__lt.3)", "(Foo.a$__ret.4)", "(Foo.b$__ret.1)", "(Foo.b$__ret.4)"] {
+			assert!(out.contains(label), "{} not in {}", label, out);
+		}
+
+		// The code of a function should not depend on the functions before it.
+		assert!(out.ends_with(&code(&["Foo.b"])));
+	}
+
+	#[test]
+	fn test_vm_labels(){
+		// Labels should be scoped by the function label whether or not the function is qualified.
+		for name in ["Foo.a", "a"] {
+			let (_, ctx) = function_ins(name);
+			let mut coder = Coder::new(CoderOptions::default());
+			let mut out = vec![];
+			let label = CompactString::from("LOOP");
+			for ins in [VmIns::Label{label: label.clone()}, VmIns::Goto{label: label.clone()}, VmIns::IfGoto{label}] {
+				coder.write_vm_ins(&mut out, ins, &ctx).unwrap();
+			}
+			let out = String::from_utf8(out).unwrap();
+			assert_eq!(out.matches("Foo.a$LOOP").count(), 3, "{}", out);
+			assert!(!out.contains("Foo.Foo"), "{}", out);
+		}
+	}
+
+	#[test]
+	fn test_generated_labels_are_distinct(){
+		// Labels of the vm code should never be declared twice by a label named like a generated one.
+		for name in ["", "a"] {
+			let mut ctx = InsContext::new();
+			ctx.vm_file_name = CompactString::from("Prog");
+			ctx.vm_function_name = CompactString::from(name);
+			let mut coder = Coder::new(CoderOptions::default());
+			let mut out = vec![];
+			for ins in [VmIns::Eq, VmIns::Call{function: CompactString::from("Bar.c"), args_count: 0},
+				VmIns::Label{label: CompactString::from("eq.1")}, VmIns::Label{label: CompactString::from("ret.2")}] {
+				coder.write_vm_ins(&mut out, ins, &ctx).unwrap();
+			}
+			let out = String::from_utf8(out).unwrap();
+			let mut labels: Vec<&str> = out.lines().filter(|line| line.starts_with('(')).collect();
+			assert_eq!(labels.len(), 4, "{}", out);
+			labels.sort();
+			labels.dedup();
+			assert_eq!(labels.len(), 4, "{}", out);
+		}
+	}
+
+	#[test]
+	fn test_templates(){
+		let code = |templates: TemplateSet, ins: VmIns| {
+			let (_, ctx) = function_ins("Foo.a");
+			let mut coder = Coder::new(CoderOptions{templates, ..CoderOptions::default()});
+			let mut out = vec![];
+			coder.write_vm_ins(&mut out, ins, &ctx).unwrap();
+			String::from_utf8(out).unwrap()
+		};
+		let push = |segment, index| VmIns::Push{segment, index};
+		let pop = |segment, index| VmIns::Pop{segment, index};
+		let function = |locals_count| VmIns::Function{name: CompactString::from("Foo.a"), locals_count};
+
+		// Specialised templates should be written exactly, with their segment and computation.
+		for set in [TemplateSet::Size, TemplateSet::Speed] {
+			assert_eq!(code(set, push(VmSeg::Constant, 0)), "@SP\nM=M+1\nA=M-1\nM=0\n");
+			assert_eq!(code(set, push(VmSeg::Constant, 1)), "@SP\nM=M+1\nA=M-1\nM=1\n");
+			assert_eq!(code(set, push(VmSeg::Local, 0)), "@LCL\nA=M\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n");
+			assert_eq!(code(set, push(VmSeg::Argument, 1)), "@ARG\nA=M+1\nD=M\n@SP\nAM=M+1\nA=A-1\nM=D\n");
+			assert_eq!(code(set, pop(VmSeg::This, 0)), "@SP\nM=M-1\nA=M\nD=M\n@THIS\nD=D+M\n@SP\nA=M\nA=M\nA=D-A\nM=D-A\n");
+			assert_eq!(code(set, pop(VmSeg::That, 1)), "@SP\nM=M-1\nA=M\nD=M+1\n@THAT\nD=D+M\n@SP\nA=M\nA=M\nA=D-A\nM=D-A\n");
+			assert_eq!(code(set, VmIns::Inc{segment: VmSeg::Local, index: 0}), "@LCL\nA=M\nM=M+1\n");
+			assert_eq!(code(set, VmIns::Dec{segment: VmSeg::Local, index: 1}), "@LCL\nA=M+1\nM=M-1\n");
+
+			// Other indices should use the general templates.
+			assert_eq!(code(set, push(VmSeg::Constant, 2)), "@2\nD=A\n@SP\nM=M+1\nA=M-1\nM=D\n");
+			assert_eq!(code(set, VmIns::Inc{segment: VmSeg::Local, index: 2}), "@2\nD=A\n@LCL\nA=D+M\nM=M+1\n");
+			assert_eq!(code(set, function(0)), "(Foo.a)\n");
+			assert_eq!(code(set, function(2)), "(Foo.a)\n@SP\nAM=M+1\nA=A-1\nM=0\n@SP\nAM=M+1\nA=A-1\nM=0\n");
+		}
+
+		// Prologues should be unrolled up to the limit of the set, and loop beyond it.
+		let lines = |set, locals_count| code(set, function(locals_count)).lines().count();
+		assert_eq!((lines(TemplateSet::Size, 3), lines(TemplateSet::Size, 20)), (11, 11));
+		assert_eq!((lines(TemplateSet::Speed, 8), lines(TemplateSet::Speed, 9)), (33, 11));
+		assert!(code(TemplateSet::Speed, function(9)).contains("(__LOOP_Foo.a)\n"));
+	}
+}
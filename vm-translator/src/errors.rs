@@ -2,12 +2,14 @@ use compact_str::CompactString;
 use core::ops::Range;
 use std::path::PathBuf;
 use std::io;
-use crate::tokenizer::{VmToken, VmSeg};
+use crate::tokenizer::{VmToken, VmSeg, VmCmd};
+use crate::parser::RESERVED_LABEL_PREFIX;
 use crate::InsContext;
 
 #[derive(Debug)]
 pub enum TokenError {
-	InvalidToken{word: CompactString},
+	InvalidToken{word: CompactString, span: Range<usize>},
+	IntOverflow{word: CompactString, span: Range<usize>},
 	IoError(io::Error),
 }
 
@@ -23,6 +25,16 @@ pub enum ParseError {
 	ExpectedIdentifier{received: Option<VmToken>},
 	ExpectedIntConst{received: Option<VmToken>},
 	ExpectedSegment{received: Option<VmToken>},
+	IndexOutOfBounds{segment: VmSeg, index: u16, bounds: Range<usize>, span: Range<usize>},
+	DuplicateLabel{label: CompactString, span: Range<usize>},
+	UndefinedLabel{label: CompactString, span: Range<usize>},
+	InvalidIdentifier{identifier: CompactString, span: Range<usize>},
+	ReservedLabel{label: CompactString, span: Range<usize>},
+	FunctionNotInFile{name: CompactString, file: CompactString, span: Range<usize>},
+	UnqualifiedCall{function: CompactString, span: Range<usize>},
+	ExtensionDisabled{command: VmCmd, span: Range<usize>},
+	PopConstant{span: Range<usize>},
+	ConstantSegment{command: VmCmd, span: Range<usize>},
 	TokenError(TokenError),
 }
 
@@ -32,8 +44,10 @@ impl From<TokenError> for ParseError {
 	}
 }
 
+#[derive(Debug)]
 pub enum CodeError {
 	IndexOutOfBounds{segment: VmSeg, index: u16, bounds: Range<usize>},
+	CounterOverflow{function: CompactString, address: usize},
 	IoError(io::Error),
 }
 
@@ -43,16 +57,23 @@ impl From<io::Error> for CodeError {
 	}
 }
 
+pub enum TranslationWarning {
+	UnusedLabel{label: CompactString, line: String, line_num: usize, span: Range<usize>},
+}
+
+#[derive(Default)]
 pub struct TranslationContext {
 	pub filepath: PathBuf,
 	pub ins_ctx: InsContext,
 	pub line: String,
 	pub line_num: usize,
+	pub warnings: Vec<TranslationWarning>,
 }
 
 impl TranslationContext {
 	pub fn new() -> Self {
-		TranslationContext{filepath: PathBuf::new(), ins_ctx: InsContext::new(), line: String::new(), line_num: 0}
+		TranslationContext{filepath: PathBuf::new(), ins_ctx: InsContext::new(), line: String::new(), line_num: 0,
+			warnings: vec![]}
 	}
 }
 
@@ -84,6 +105,17 @@ fn write_error(msg: &str, ctx: &TranslationContext) {
 	println!("{}, on line:\n[{}] {}", msg, ctx.line_num, ctx.line);
 }
 
+fn write_span_error(msg: &str, span: Range<usize>, ctx: &TranslationContext) {
+	write_span_msg(msg, span, &ctx.line, ctx.line_num);
+}
+
+fn write_span_msg(msg: &str, span: Range<usize>, line: &str, line_num: usize) {
+	let line = line.trim_end();
+	let prefix = format!("[{}] ", line_num);
+	let indent: String = line[..span.start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+	println!("{}, on line:\n{}{}\n{}{}{}", msg, prefix, line, " ".repeat(prefix.len()), indent, "^".repeat(span.len()));
+}
+
 fn write_io_error(e: io::Error){
 	println!("io error: {}", e);
 }
@@ -91,8 +123,11 @@ fn write_io_error(e: io::Error){
 fn write_token_error(e: TokenError, ctx: &TranslationContext){
 	match e {
 		TokenError::IoError(e) => write_io_error(e),
-		TokenError::InvalidToken{word} => {
-			write_error(format!("token error: invalid token '{}'", word).as_str(), ctx);
+		TokenError::InvalidToken{word, span} => {
+			write_span_error(format!("token error: invalid token '{}'", word).as_str(), span, ctx);
+		},
+		TokenError::IntOverflow{word, span} => {
+			write_span_error(format!("token error: integer '{}' overflows a 16-bit word", word).as_str(), span, ctx);
 		},
 	}
 }
@@ -111,6 +146,45 @@ fn write_parse_error(e: ParseError, ctx: &TranslationContext){
 		ParseError::ExpectedSegment{received} => {
 			write_error(format!("parse error: expected segment, received {}", received.unwrap()).as_str(), ctx);
 		},
+		ParseError::IndexOutOfBounds{segment, index, bounds, span} => {
+			let msg = format!("parse error: index '{}' overflows segment '{}'; segment bounds '[{},{}]'",
+				index, segment, bounds.start, bounds.end);
+			write_span_error(&msg, span, ctx);
+		},
+		ParseError::DuplicateLabel{label, span} => {
+			write_span_error(format!("parse error: label '{}' is already declared in this function", label).as_str(), span, ctx);
+		},
+		ParseError::UndefinedLabel{label, span} => {
+			write_span_error(format!("parse error: label '{}' is not declared in this function", label).as_str(), span, ctx);
+		},
+		ParseError::InvalidIdentifier{identifier, span} => {
+			let msg = format!("parse error: invalid identifier '{}'; identifiers may only contain letters, digits, '_', '.' and ':'",
+				identifier);
+			write_span_error(&msg, span, ctx);
+		},
+		ParseError::ReservedLabel{label, span} => {
+			let msg = format!("parse error: label '{}' may not start with '{}', which is reserved for generated labels",
+				label, RESERVED_LABEL_PREFIX);
+			write_span_error(&msg, span, ctx);
+		},
+		ParseError::FunctionNotInFile{name, file, span} => {
+			let msg = format!("parse error: function '{}' must be named '{}.<name>' to be declared in file '{}.vm'",
+				name, file, file);
+			write_span_error(&msg, span, ctx);
+		},
+		ParseError::UnqualifiedCall{function, span} => {
+			write_span_error(format!("parse error: call to '{}' must be qualified as '<File>.<name>'", function).as_str(), span, ctx);
+		},
+		ParseError::ExtensionDisabled{command, span} => {
+			write_span_error(format!("parse error: '{}' is an extension to the vm language; enable with --ext", command).as_str(), span, ctx);
+		},
+		ParseError::PopConstant{span} => {
+			let msg = "parse error: cannot pop to segment 'constant'; allow with --allow-pop-constant to discard the value";
+			write_span_error(msg, span, ctx);
+		},
+		ParseError::ConstantSegment{command, span} => {
+			write_span_error(format!("parse error: '{}' cannot modify segment 'constant'", command).as_str(), span, ctx);
+		},
 		ParseError::TokenError(e) => {
 			write_token_error(e, ctx);
 		},
@@ -125,6 +199,11 @@ fn write_code_error(e: CodeError, ctx: &TranslationContext){
 				index, segment, bounds.start, bounds.end);
 			write_error(&msg, ctx);
 		},
+		CodeError::CounterOverflow{function, address} => {
+			let msg = format!("code error: instrumentation counter for function '{}' at address '{}' overlaps the screen",
+				function, address);
+			write_error(&msg, ctx);
+		},
 	}
 }
 
@@ -136,3 +215,10 @@ pub fn write_translation_error(e: TranslationError, ctx: &TranslationContext) {
 	}
 }
 
+pub fn write_translation_warning(w: &TranslationWarning) {
+	match w {
+		TranslationWarning::UnusedLabel{label, line, line_num, span} => {
+			write_span_msg(format!("warning: label '{}' is never targeted", label).as_str(), span.clone(), line, *line_num);
+		},
+	}
+}
@@ -0,0 +1,220 @@
+use std::path::PathBuf;
+use n2t_assembler::generator::Rng;
+use crate::VmSource;
+
+/// Options of a generated program.
+#[derive(Debug, Clone)]
+pub struct GenOptions {
+	/// Number of files the functions are spread across.
+	pub file_count: usize,
+	/// Number of functions, excluding Sys.init.
+	pub function_count: usize,
+	/// Upper bound on the number of instructions generated per block of a function body.
+	pub block_size: usize,
+	/// Seed of the program; a failing seed of a test regenerates the same files to debug.
+	pub seed: u64,
+}
+
+impl Default for GenOptions {
+	fn default() -> Self {
+		GenOptions{file_count: 2, function_count: 6, block_size: 12, seed: 1}
+	}
+}
+
+const STATIC_COUNT: usize = 8;
+const TEMP_COUNT: usize = 8;
+
+struct Function {
+	name: String,
+	args_count: usize,
+	locals_count: usize,
+}
+
+struct FunctionGen<'a> {
+	rng: &'a mut Rng,
+	functions: &'a [Function],
+	index: usize,
+	label_count: usize,
+	depth: usize,
+	code: String,
+}
+
+impl<'a> FunctionGen<'a> {
+	fn emit(&mut self, line: &str) {
+		self.code.push_str(line);
+		self.code.push('\n');
+	}
+
+	fn push_any(&mut self) {
+		let this = &self.functions[self.index];
+		let (args_count, locals_count) = (this.args_count, this.locals_count);
+		let line = match self.rng.below(5) {
+			0 if args_count > 0 => format!("push argument {}", self.rng.below(args_count)),
+			1 if locals_count > 0 => format!("push local {}", self.rng.below(locals_count)),
+			2 => format!("push static {}", self.rng.below(STATIC_COUNT)),
+			3 => format!("push temp {}", self.rng.below(TEMP_COUNT)),
+			_ => format!("push constant {}", self.rng.below(32768)),
+		};
+		self.emit(&line);
+		self.depth += 1;
+	}
+
+	fn pop_any(&mut self) {
+		let locals_count = self.functions[self.index].locals_count;
+		let line = match self.rng.below(3) {
+			0 if locals_count > 0 => format!("pop local {}", self.rng.below(locals_count)),
+			1 => format!("pop static {}", self.rng.below(STATIC_COUNT)),
+			_ => format!("pop temp {}", self.rng.below(TEMP_COUNT)),
+		};
+		self.emit(&line);
+		self.depth -= 1;
+	}
+
+	/// Calls are only made to functions defined after the caller, so every program terminates.
+	fn call_any(&mut self) {
+		let callee = self.index + 1 + self.rng.below(self.functions.len() - self.index - 1);
+		for _ in 0..self.functions[callee].args_count {
+			self.push_any();
+		}
+		let line = format!("call {} {}", self.functions[callee].name, self.functions[callee].args_count);
+		self.emit(&line);
+		self.depth = self.depth + 1 - self.functions[callee].args_count;
+	}
+
+	/// A block of random instructions which leaves the stack as deep as it found it.
+	fn block(&mut self, size: usize) {
+		let base = self.depth;
+		for _ in 0..size {
+			let can_call = self.index + 1 < self.functions.len();
+			match self.rng.below(10) {
+				0..=2 => self.push_any(),
+				3 if self.depth > base => self.pop_any(),
+				4 if self.depth > base => {
+					let op = ["neg", "not"][self.rng.below(2)];
+					self.emit(op);
+				},
+				5 | 6 if self.depth > base + 1 => {
+					let op = ["add", "sub", "and", "or", "eq", "lt", "gt"][self.rng.below(7)];
+					self.emit(op);
+					self.depth -= 1;
+				},
+				7 if can_call => self.call_any(),
+				8 if size > 2 => {
+					// Skip a nested block on a random condition; labels only ever jump forward.
+					self.label_count += 1;
+					let label = format!("SKIP{}", self.label_count);
+					self.push_any();
+					self.emit(&format!("if-goto {}", label));
+					self.depth -= 1;
+					let nested = size / 2;
+					self.block(nested);
+					self.emit(&format!("label {}", label));
+				},
+				_ => self.push_any(),
+			}
+		}
+		while self.depth > base {
+			self.pop_any();
+		}
+	}
+}
+
+/// Generate a random well-formed VM program of virtual files. Functions are stack balanced,
+/// only call functions defined after them, and only jump forward, so every program returns
+/// from Sys.init to a `label HALT; goto HALT` loop.
+pub fn generate_vm(options: &GenOptions) -> Vec<VmSource> {
+	let mut rng = Rng::new(options.seed);
+	let mut functions = vec![Function{name: "Sys.init".to_string(), args_count: 0, locals_count: 0}];
+	for i in 0..options.function_count {
+		let file = rng.below(options.file_count);
+		functions.push(Function{name: format!("File{}.f{}", file, i), args_count: rng.below(4), locals_count: rng.below(4)});
+	}
+
+	let mut files: Vec<(String, String)> = vec![("Sys".to_string(), String::new())];
+	for index in 0..functions.len() {
+		let mut gen = FunctionGen{rng: &mut rng, functions: &functions, index, label_count: 0, depth: 0, code: String::new()};
+		let this = &functions[index];
+		gen.emit(&format!("function {} {}", this.name, this.locals_count));
+		let size = 1 + gen.rng.below(options.block_size);
+		gen.block(size);
+		if index == 0 {
+			if functions.len() > 1 {
+				gen.call_any();
+				gen.pop_any();
+			}
+			gen.emit("label HALT");
+			gen.emit("goto HALT");
+		} else {
+			gen.push_any();
+			gen.emit("return");
+		}
+
+		let file = this.name.split('.').next().unwrap();
+		let code = gen.code;
+		match files.iter_mut().find(|(name, _)| name == file) {
+			Some((_, file_code)) => file_code.push_str(&code),
+			None => files.push((file.to_string(), code)),
+		}
+	}
+
+	files.into_iter()
+		.map(|(name, code)| VmSource::Virtual{path: PathBuf::from(format!("{}.vm", name)), code})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::coder::*;
+	use crate::errors::*;
+	use crate::callgraph::CallGraph;
+	use crate::parser::ParserOptions;
+	use crate::interpreter::{Interpreter, Halt};
+	use crate::machine::Machine;
+	use super::*;
+
+	#[test]
+	fn test_generated_programs_translate(){
+		for seed in 0..50 {
+			let options = GenOptions{seed, ..GenOptions::default()};
+			let program = generate_vm(&options);
+			let code = |program: &[VmSource]| program.iter().map(|s| match s {
+				VmSource::Virtual{code, ..} => code.clone(),
+				VmSource::File(_) => unreachable!(),
+			}).collect::<String>();
+			assert_eq!(code(&program), code(&generate_vm(&options)), "programs should be reproducible");
+
+			let mut ctx = TranslationContext::new();
+			let mut coder = Coder::new(CoderOptions::default());
+			let options = ParserOptions{strict: true, ..ParserOptions::default()};
+			let result = crate::translate(program, vec![], &mut ctx, &mut coder, &mut CallGraph::new(), options);
+			assert!(result.is_ok(), "seed {}: {}", seed, code(&generate_vm(&GenOptions{seed, ..GenOptions::default()})));
+			assert!(ctx.warnings.is_empty(), "seed {}", seed);
+		}
+	}
+
+	#[test]
+	fn test_generated_programs_execute(){
+		for seed in 0..50 {
+			let options = GenOptions{seed, ..GenOptions::default()};
+			let mut vm = Interpreter::new(&generate_vm(&options)).unwrap();
+			assert_eq!(vm.run(1_000_000), Ok(Halt::HaltLoop), "seed {}", seed);
+
+			let mut out = vec![];
+			let mut ctx = TranslationContext::new();
+			let mut coder = Coder::new(CoderOptions::default());
+			let result = crate::translate(generate_vm(&options), &mut out, &mut ctx, &mut coder, &mut CallGraph::new(), ParserOptions::default());
+			assert!(result.is_ok(), "seed {}", seed);
+			let mut machine = Machine::new(&String::from_utf8(out).unwrap()).unwrap();
+			assert_eq!(machine.run_until("Sys.init$HALT", 10_000_000), Ok(()), "seed {}", seed);
+
+			// The translation should leave the same state as the interpreter; only Sys.init's
+			// frame, of 5 words, on the stack.
+			assert_eq!(machine.ram[0], 261, "seed {}", seed);
+			assert_eq!(machine.ram[5..13], vm.temp, "seed {}", seed);
+			for ((file, index), value) in &vm.statics {
+				let address = machine.symbol(&format!("{}.{}", file, index)).unwrap();
+				assert_eq!(machine.ram[address as usize], *value, "seed {}; static {}.{}", seed, file, index);
+			}
+		}
+	}
+}
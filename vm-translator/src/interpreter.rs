@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Cursor};
+use std::fs;
+use compact_str::CompactString;
+use crate::VmSource;
+use crate::coder::function_label;
+use crate::tokenizer::*;
+use crate::parser::*;
+
+/// Why the interpreter stopped.
+#[derive(Debug, PartialEq)]
+pub enum Halt {
+	/// A `goto` to the label immediately before it; the conventional end of a program.
+	HaltLoop,
+	/// Returned from the outermost function, or ran off the end of the program.
+	Finished,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InterpretError {
+	Parse(String),
+	UnknownFunction(CompactString),
+	UnknownLabel(CompactString),
+	StackUnderflow{pc: usize},
+	SegmentOutOfBounds{pc: usize},
+	StepLimit,
+}
+
+struct Frame {
+	return_pc: usize,
+	function: CompactString,
+	arg_base: usize,
+	lcl_base: usize,
+	locals_count: usize,
+	this: i16,
+	that: i16,
+}
+
+/// A reference interpreter of the VM language, written to be obviously correct rather than
+/// fast; the oracle against which translated programs are compared. Segments are modelled
+/// directly rather than in RAM: the stack holds the arguments and locals of each frame, static
+/// variables are keyed by file and index, and only `this`/`that` address a flat RAM.
+pub struct Interpreter {
+	program: Vec<(CompactString, VmIns)>,
+	functions: HashMap<CompactString, usize>,
+	labels: HashMap<(CompactString, CompactString), usize>,
+	pub stack: Vec<i16>,
+	pub statics: HashMap<(CompactString, u16), i16>,
+	pub temp: [i16; 8],
+	pub ram: Vec<i16>,
+	frames: Vec<Frame>,
+	pc: usize,
+}
+
+impl Interpreter {
+	/// Load a program; execution starts at Sys.init when defined, otherwise at the first
+	/// instruction.
+	pub fn new(sources: &[VmSource]) -> Result<Self, InterpretError> {
+		let mut program = vec![];
+		let mut functions = HashMap::new();
+		let mut labels = HashMap::new();
+		for source in sources {
+			let file: CompactString = source.path().file_stem().unwrap().to_string_lossy().into();
+			let code = match source {
+				VmSource::File(path) => fs::read_to_string(path).map_err(|e| InterpretError::Parse(e.to_string()))?,
+				VmSource::Virtual{code, ..} => code.clone(),
+			};
+			let parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(code))));
+			let mut function = CompactString::new("");
+			for ins in parser {
+				let ins = ins.map_err(|e| InterpretError::Parse(format!("{:?}", e)))?;
+				match &ins {
+					VmIns::Function{name, ..} => {
+						function = function_label(&file, name);
+						functions.insert(function.clone(), program.len());
+					},
+					VmIns::Label{label} => {
+						labels.insert((function.clone(), label.clone()), program.len());
+					},
+					_ => (),
+				}
+				program.push((file.clone(), ins));
+			}
+		}
+		let mut interpreter = Interpreter{program, functions, labels, stack: vec![], statics: HashMap::new(), temp: [0; 8],
+			ram: vec![0; 32768], frames: vec![], pc: 0};
+		if interpreter.functions.contains_key("Sys.init") {
+			interpreter.call(&CompactString::from("Sys.init"), 0, usize::MAX)?;
+		}
+		Ok(interpreter)
+	}
+
+	fn pop(&mut self) -> Result<i16, InterpretError> {
+		let floor = self.frames.last().map_or(0, |f| f.lcl_base);
+		if self.stack.len() <= floor {
+			return Err(InterpretError::StackUnderflow{pc: self.pc});
+		}
+		Ok(self.stack.pop().unwrap())
+	}
+
+	fn call(&mut self, function: &CompactString, args_count: u16, return_pc: usize) -> Result<(), InterpretError> {
+		let pc = *self.functions.get(function).ok_or_else(|| InterpretError::UnknownFunction(function.clone()))?;
+		if self.stack.len() < args_count as usize {
+			return Err(InterpretError::StackUnderflow{pc: self.pc});
+		}
+		let (this, that) = self.frames.last().map_or((0, 0), |f| (f.this, f.that));
+		let arg_base = self.stack.len() - args_count as usize;
+		self.frames.push(Frame{return_pc, function: function.clone(), arg_base, lcl_base: self.stack.len(), locals_count: 0,
+			this, that});
+		self.pc = pc;
+		Ok(())
+	}
+
+	fn function(&self) -> CompactString {
+		self.frames.last().map_or(CompactString::new(""), |f| f.function.clone())
+	}
+
+	fn segment_slot(&mut self, file: &CompactString, segment: VmSeg, index: u16) -> Result<&mut i16, InterpretError> {
+		let pc = self.pc;
+		let (arg_base, lcl_base, locals_count, this, that) = self.frames.last()
+			.map_or((0, 0, 0, 0, 0), |f| (f.arg_base, f.lcl_base, f.locals_count, f.this, f.that));
+		let address = |base: i16| (base as u16 as usize) + index as usize;
+		let index = index as usize;
+		let slot = match segment {
+			VmSeg::Argument if arg_base + index < lcl_base => self.stack.get_mut(arg_base + index),
+			VmSeg::Local if index < locals_count => self.stack.get_mut(lcl_base + index),
+			VmSeg::Static => Some(self.statics.entry((file.clone(), index as u16)).or_insert(0)),
+			VmSeg::Temp => self.temp.get_mut(index),
+			VmSeg::This => self.ram.get_mut(address(this)),
+			VmSeg::That => self.ram.get_mut(address(that)),
+			VmSeg::Pointer => match (self.frames.last_mut(), index) {
+				(Some(f), 0) => Some(&mut f.this),
+				(Some(f), 1) => Some(&mut f.that),
+				_ => None,
+			},
+			VmSeg::Argument | VmSeg::Local | VmSeg::Constant => None,
+		};
+		slot.ok_or(InterpretError::SegmentOutOfBounds{pc})
+	}
+
+	fn binary(&mut self, op: impl Fn(i16, i16) -> i16) -> Result<(), InterpretError> {
+		let y = self.pop()?;
+		let x = self.pop()?;
+		self.stack.push(op(x, y));
+		Ok(())
+	}
+
+	fn unary(&mut self, op: impl Fn(i16) -> i16) -> Result<(), InterpretError> {
+		let x = self.pop()?;
+		self.stack.push(op(x));
+		Ok(())
+	}
+
+	/// Execute a single instruction; returns Some when the program has halted.
+	pub fn step(&mut self) -> Result<Option<Halt>, InterpretError> {
+		let Some((file, ins)) = self.program.get(self.pc).cloned() else {
+			return Ok(Some(Halt::Finished));
+		};
+		let mut next_pc = self.pc + 1;
+		let truth = |b: bool| if b { -1 } else { 0 };
+		match ins {
+			VmIns::Function{locals_count, ..} => {
+				self.stack.extend(std::iter::repeat_n(0, locals_count as usize));
+				if let Some(frame) = self.frames.last_mut() {
+					frame.locals_count = locals_count as usize;
+				}
+			},
+			VmIns::Call{function, args_count} => {
+				self.call(&function_label(&file, &function), args_count, next_pc)?;
+				next_pc = self.pc;
+			},
+			VmIns::CallIndirect{..} => return Err(InterpretError::UnknownFunction(CompactString::from("call-indirect"))),
+			VmIns::Return => {
+				let value = self.pop()?;
+				let frame = self.frames.pop().unwrap();
+				self.stack.truncate(frame.arg_base);
+				self.stack.push(value);
+				if self.frames.is_empty() {
+					return Ok(Some(Halt::Finished));
+				}
+				next_pc = frame.return_pc;
+			},
+			VmIns::Push{segment: VmSeg::Constant, index} => self.stack.push(index as i16),
+			VmIns::Push{segment, index} => {
+				let value = *self.segment_slot(&file, segment, index)?;
+				self.stack.push(value);
+			},
+			VmIns::Pop{segment, index} => {
+				let value = self.pop()?;
+				*self.segment_slot(&file, segment, index)? = value;
+			},
+			VmIns::Inc{segment, index} => {
+				let slot = self.segment_slot(&file, segment, index)?;
+				*slot = slot.wrapping_add(1);
+			},
+			VmIns::Dec{segment, index} => {
+				let slot = self.segment_slot(&file, segment, index)?;
+				*slot = slot.wrapping_sub(1);
+			},
+			VmIns::Label{..} => (),
+			VmIns::Goto{label} => {
+				let target = self.label(&label)?;
+				if target + 1 == self.pc {
+					return Ok(Some(Halt::HaltLoop));
+				}
+				next_pc = target;
+			},
+			VmIns::IfGoto{label} => {
+				if self.pop()? != 0 {
+					next_pc = self.label(&label)?;
+				}
+			},
+			VmIns::Add => self.binary(|x, y| x.wrapping_add(y))?,
+			VmIns::Sub => self.binary(|x, y| x.wrapping_sub(y))?,
+			VmIns::Neg => self.unary(|x| x.wrapping_neg())?,
+			VmIns::And => self.binary(|x, y| x & y)?,
+			VmIns::Or => self.binary(|x, y| x | y)?,
+			VmIns::Not => self.unary(|x| !x)?,
+			VmIns::Eq => self.binary(|x, y| truth(x == y))?,
+			VmIns::Lt => self.binary(|x, y| truth(x < y))?,
+			VmIns::Gt => self.binary(|x, y| truth(x > y))?,
+		}
+		self.pc = next_pc;
+		Ok(None)
+	}
+
+	fn label(&self, label: &CompactString) -> Result<usize, InterpretError> {
+		self.labels.get(&(self.function(), label.clone())).copied().ok_or_else(|| InterpretError::UnknownLabel(label.clone()))
+	}
+
+	/// Run until the program halts, or fail after `max_steps` instructions.
+	pub fn run(&mut self, max_steps: usize) -> Result<Halt, InterpretError> {
+		for _ in 0..max_steps {
+			if let Some(halt) = self.step()? {
+				return Ok(halt);
+			}
+		}
+		Err(InterpretError::StepLimit)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::PathBuf;
+	use crate::generator::*;
+	use super::*;
+
+	fn program(files: &[(&str, &str)]) -> Vec<VmSource> {
+		files.iter().map(|(path, code)| VmSource::Virtual{path: PathBuf::from(path), code: code.to_string()}).collect()
+	}
+
+	#[test]
+	fn test_function_calls(){
+		let sources = program(&[
+			("Sys.vm", "function Sys.init 0\npush constant 7\npush constant 3\ncall Main.f 2\npop static 0\nlabel HALT\ngoto HALT\n"),
+			("Main.vm", "function Main.f 1\npush argument 0\npush argument 1\nsub\npop local 0\npush local 0\nneg\nreturn\n"),
+		]);
+		let mut vm = Interpreter::new(&sources).unwrap();
+		assert_eq!(vm.run(100), Ok(Halt::HaltLoop));
+		assert_eq!(vm.statics[&(CompactString::from("Sys"), 0)], -4);
+		assert!(vm.stack.is_empty());
+	}
+
+	#[test]
+	fn test_unqualified_functions(){
+		// Unqualified functions should be qualified by their file, whether declared or called.
+		let sources = program(&[
+			("Sys.vm", "function Sys.init 0\ncall Main.f 0\npop static 0\nlabel HALT\ngoto HALT\n"),
+			("Main.vm", "function f 0\ncall g 0\nreturn\nfunction g 0\nlabel L\npush constant 5\nreturn\n"),
+		]);
+		let mut vm = Interpreter::new(&sources).unwrap();
+		assert_eq!(vm.run(100), Ok(Halt::HaltLoop));
+		assert_eq!(vm.statics[&(CompactString::from("Sys"), 0)], 5);
+	}
+
+	#[test]
+	fn test_recursion(){
+		let fib = "\
+			function Main.fib 0\n\
+			push argument 0\npush constant 2\nlt\nif-goto BASE\n\
+			push argument 0\npush constant 1\nsub\ncall Main.fib 1\n\
+			push argument 0\npush constant 2\nsub\ncall Main.fib 1\n\
+			add\nreturn\n\
+			label BASE\npush argument 0\nreturn\n\
+			function Main.main 0\npush constant 20\ncall Main.fib 1\nreturn\n";
+		let sources = program(&[("Main.vm", fib), ("Sys.vm", "function Sys.init 0\ncall Main.main 0\nreturn\n")]);
+		let mut vm = Interpreter::new(&sources).unwrap();
+		assert_eq!(vm.run(1_000_000), Ok(Halt::Finished));
+		assert_eq!(vm.stack, vec![6765]);
+	}
+
+	#[test]
+	fn test_errors(){
+		let run = |code: &str| Interpreter::new(&program(&[("Sys.vm", code)])).and_then(|mut vm| vm.run(100));
+		assert_eq!(run("function Sys.init 0\ncall Foo.bar 0\n"), Err(InterpretError::UnknownFunction(CompactString::from("Foo.bar"))));
+		assert_eq!(run("function Sys.init 0\nadd\n"), Err(InterpretError::StackUnderflow{pc: 1}));
+		assert_eq!(run("function Sys.init 1\npush local 1\n"), Err(InterpretError::SegmentOutOfBounds{pc: 1}));
+		assert_eq!(run("function Sys.init 0\nlabel L\ngoto L2\n"), Err(InterpretError::UnknownLabel(CompactString::from("L2"))));
+		assert_eq!(run("function Sys.init 0\nlabel L\npush constant 0\npop temp 0\ngoto L\n"), Err(InterpretError::StepLimit));
+	}
+
+	#[test]
+	fn test_generated_programs_halt(){
+		for seed in 0..50 {
+			let mut vm = Interpreter::new(&generate_vm(&GenOptions{seed, ..GenOptions::default()})).unwrap();
+			assert_eq!(vm.run(1_000_000), Ok(Halt::HaltLoop), "seed {}", seed);
+			assert!(vm.stack.is_empty(), "seed {}", seed);
+		}
+	}
+}
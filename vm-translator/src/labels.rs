@@ -0,0 +1,106 @@
+use core::ops::Range;
+use compact_str::CompactString;
+use crate::errors::*;
+
+struct LabelUse {
+	label: CompactString,
+	line: String,
+	line_num: usize,
+	span: Range<usize>,
+}
+
+impl LabelUse {
+	fn new(label: &CompactString, ctx: &TranslationContext, span: Range<usize>) -> Self {
+		LabelUse{label: label.clone(), line: ctx.line.clone(), line_num: ctx.line_num, span}
+	}
+}
+
+/// Verifies the labels of a single function scope; labels are only visible within the
+/// function which declares them, so every goto/if-goto target must be declared in the
+/// same function.
+#[derive(Default)]
+pub struct LabelChecker {
+	declared: Vec<LabelUse>,
+	targeted: Vec<LabelUse>,
+}
+
+impl LabelChecker {
+	pub fn new() -> Self {
+		LabelChecker{declared: vec![], targeted: vec![]}
+	}
+
+	pub fn declare(&mut self, label: &CompactString, ctx: &TranslationContext, span: Range<usize>) -> Result<(), ParseError> {
+		if self.declared.iter().any(|l| l.label == *label) {
+			return Err(ParseError::DuplicateLabel{label: label.clone(), span});
+		}
+		self.declared.push(LabelUse::new(label, ctx, span));
+		Ok(())
+	}
+
+	pub fn target(&mut self, label: &CompactString, ctx: &TranslationContext, span: Range<usize>) {
+		self.targeted.push(LabelUse::new(label, ctx, span));
+	}
+
+	/// Check the labels of the scope just finished and reset for the next scope. Undefined
+	/// targets are errors, reported against the line of the goto; labels which are never
+	/// targeted are warnings.
+	pub fn end_scope(&mut self, ctx: &mut TranslationContext) -> Result<(), ParseError> {
+		let declared = std::mem::take(&mut self.declared);
+		let targeted = std::mem::take(&mut self.targeted);
+		for l in &declared {
+			if !targeted.iter().any(|t| t.label == l.label) {
+				ctx.warnings.push(TranslationWarning::UnusedLabel{label: l.label.clone(), line: l.line.clone(),
+					line_num: l.line_num, span: l.span.clone()});
+			}
+		}
+		if let Some(target) = targeted.into_iter().find(|t| !declared.iter().any(|l| l.label == t.label)) {
+			ctx.line = target.line;
+			ctx.line_num = target.line_num;
+			return Err(ParseError::UndefinedLabel{label: target.label, span: target.span});
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at_line(ctx: &mut TranslationContext, line_num: usize, line: &str) {
+		ctx.line = line.to_string();
+		ctx.line_num = line_num;
+	}
+
+	#[test]
+	fn test_label_scopes(){
+		let mut ctx = TranslationContext::new();
+		let mut checker = LabelChecker::new();
+		let loop_label = CompactString::from("LOOP");
+		let end_label = CompactString::from("END");
+
+		// Targets may appear before or after their declaration.
+		at_line(&mut ctx, 1, "goto LOOP");
+		checker.target(&loop_label, &ctx, 5..9);
+		at_line(&mut ctx, 2, "label LOOP");
+		checker.declare(&loop_label, &ctx, 6..10).unwrap();
+		at_line(&mut ctx, 3, "label END");
+		checker.declare(&end_label, &ctx, 6..9).unwrap();
+
+		// Labels may only be declared once per scope.
+		assert!(matches!(checker.declare(&loop_label, &ctx, 6..10), Err(ParseError::DuplicateLabel{..})));
+
+		// Untargeted labels should be warned about.
+		checker.end_scope(&mut ctx).unwrap();
+		assert_eq!(ctx.warnings.len(), 1);
+		assert!(matches!(&ctx.warnings[0], TranslationWarning::UnusedLabel{label, line_num: 3, ..} if *label == "END"));
+
+		// Labels should not be visible from the next scope.
+		at_line(&mut ctx, 7, "if-goto LOOP");
+		checker.target(&loop_label, &ctx, 8..12);
+		at_line(&mut ctx, 9, "return");
+		assert!(matches!(checker.end_scope(&mut ctx), Err(ParseError::UndefinedLabel{span, ..}) if span == (8..12)));
+
+		// Errors should be reported against the line of the goto.
+		assert_eq!((ctx.line_num, ctx.line.as_str()), (7, "if-goto LOOP"));
+	}
+}
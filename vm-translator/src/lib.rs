@@ -0,0 +1,124 @@
+//! The VM translator as a library; translates Hack VM code to assembly with [`translate`].
+//! Also holds the tools which check translations: a reference [`interpreter`] of VM code,
+//! a model of the Hack [`machine`] and a random program [`generator`], for differential
+//! tests of this and other implementations of the VM, such as emulators.
+
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::path::PathBuf;
+use core::ops::Range;
+use std::fs::File;
+use crate::coder::*;
+use crate::tokenizer::*;
+use crate::parser::*;
+use crate::errors::*;
+use crate::callgraph::*;
+use crate::labels::*;
+
+pub mod errors;
+pub mod tokenizer;
+pub mod parser;
+pub mod coder;
+pub mod callgraph;
+pub mod labels;
+pub mod machine;
+pub mod audit;
+pub mod generator;
+pub mod interpreter;
+
+/// A unit of vm code to translate; static variables are scoped to each source.
+#[derive(Debug)]
+pub enum VmSource {
+	File(PathBuf),
+	Virtual{path: PathBuf, code: String},
+}
+
+impl VmSource {
+	pub fn path(&self) -> &PathBuf {
+		match self {
+			VmSource::File(path) => path,
+			VmSource::Virtual{path, ..} => path,
+		}
+	}
+}
+
+/// Whether `name` has the form `File.name` of the official VM naming convention.
+fn is_qualified_name(name: &str) -> bool {
+	name.split_once('.').is_some_and(|(file, name)| !file.is_empty() && !name.is_empty())
+}
+
+/// Check the naming conventions of the official VM specification: functions are named
+/// `File.name` after the file which declares them, and calls are to such names.
+fn check_strict_names(ins: &VmIns, span: Range<usize>, ctx: &TranslationContext) -> Result<(), ParseError> {
+	let file = &ctx.ins_ctx.vm_file_name;
+	match ins {
+		VmIns::Function{name, ..} if !is_qualified_name(name) || name.split('.').next() != Some(file.as_str()) => {
+			Err(ParseError::FunctionNotInFile{name: name.clone(), file: file.clone(), span})
+		},
+		VmIns::Call{function, ..} if !is_qualified_name(function) => {
+			Err(ParseError::UnqualifiedCall{function: function.clone(), span})
+		},
+		_ => Ok(()),
+	}
+}
+
+fn translate_file<R: BufRead, W: Write>(vm_file: R, coder: &mut Coder, ctx: &mut TranslationContext,
+	out_file: &mut InsCounter<W>, call_graph: &mut CallGraph, options: ParserOptions) -> Result<(), TranslationError> {
+	let tokenizer = Tokenizer::new(vm_file);
+	let mut parser = Parser::new(tokenizer);
+	parser.set_options(options);
+	let mut labels = LabelChecker::new();
+	while let Some(ins) = parser.next() {
+		ctx.line.clear();
+		ctx.line.insert_str(0, parser.get_line());
+		ctx.line_num = parser.get_line_num();
+		let ins = ins?;
+		if options.strict {
+			check_strict_names(&ins, parser.get_ident_span(), ctx)?;
+		}
+		match ins {
+			VmIns::Function{ref name, ..} => {
+				labels.end_scope(ctx)?;
+				ctx.ins_ctx.vm_function_name = name.clone();
+				call_graph.add_function(&function_label(&ctx.ins_ctx.vm_file_name, name));
+			},
+			VmIns::Call{ref function, ..} => {
+				let file = &ctx.ins_ctx.vm_file_name;
+				call_graph.add_call(&function_label(file, &ctx.ins_ctx.vm_function_name), &function_label(file, function));
+			},
+			VmIns::Label{ref label} => labels.declare(label, ctx, parser.get_span())?,
+			VmIns::Goto{ref label} | VmIns::IfGoto{ref label} => labels.target(label, ctx, parser.get_span()),
+			_ => (),
+		}
+		let ins_count = out_file.count();
+		coder.write_vm_ins(out_file, ins, &ctx.ins_ctx)?;
+		if !ctx.ins_ctx.vm_function_name.is_empty() {
+			let function = function_label(&ctx.ins_ctx.vm_file_name, &ctx.ins_ctx.vm_function_name);
+			call_graph.add_ins_count(&function, out_file.count() - ins_count);
+		}
+	}
+	labels.end_scope(ctx)?;
+	Ok(())
+}
+
+/// Translate sources, in order, into a single assembly program.
+pub fn translate<W: Write>(in_files: Vec<VmSource>, out_file: W, ctx: &mut TranslationContext, coder: &mut Coder,
+	call_graph: &mut CallGraph, options: ParserOptions) -> Result<(), TranslationError> {
+	let out_file = &mut InsCounter::new(out_file);
+	coder.write_core_impl(out_file)?;
+	for source in in_files {
+		ctx.filepath = source.path().clone();
+		let file_stem = source.path().file_stem().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+			format!("'{}' has no file name", source.path().display())))?;
+		ctx.ins_ctx.vm_file_name = file_stem.to_string_lossy().to_string().into();
+		ctx.ins_ctx.vm_function_name.clear();
+		match source {
+			VmSource::File(path) => translate_file(BufReader::new(File::open(path)?), coder, ctx, out_file, call_graph, options)?,
+			VmSource::Virtual{code, ..} => translate_file(Cursor::new(code), coder, ctx, out_file, call_graph, options)?,
+		}
+	}
+	coder.write_dispatch_impl(out_file)?;
+	coder.write_module_init_impl(out_file)?;
+	coder.write_pruned_core_impl(out_file)?;
+	out_file.flush()?;
+	Ok(())
+}
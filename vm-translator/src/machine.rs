@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fmt;
+use n2t_assembler::{assemble_program, AssembleOptions};
+use n2t_assembler::encoder::decode_ins;
+
+pub const RAM_SIZE: usize = 32768;
+
+#[derive(Debug, PartialEq)]
+pub enum MachineError {
+	Assemble{error_count: u32},
+	InvalidIns{address: usize, word: u16},
+	UnknownLabel(String),
+	PcOutOfRange{pc: usize},
+	RamOutOfRange{pc: usize, address: usize},
+	StepLimit,
+}
+
+impl fmt::Display for MachineError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MachineError::Assemble{error_count} => write!(f, "assembly failed with {} errors", error_count),
+			MachineError::InvalidIns{address, word} => write!(f, "invalid instruction {:016b} at {}", word, address),
+			MachineError::UnknownLabel(label) => write!(f, "unknown label '{}'", label),
+			MachineError::PcOutOfRange{pc} => write!(f, "jumped outside the program to {}", pc),
+			MachineError::RamOutOfRange{pc, address} => write!(f, "wrote outside RAM to {} at {}", address, pc),
+			MachineError::StepLimit => write!(f, "did not finish; step limit reached"),
+		}
+	}
+}
+
+const C_INS_BIT: u16 = 1 << 15;
+const A_BIT: u16 = 1 << 12;
+const DEST_A_BIT: u16 = 1 << 5;
+const DEST_D_BIT: u16 = 1 << 4;
+const DEST_M_BIT: u16 = 1 << 3;
+const JLT_BIT: u16 = 1 << 2;
+const JEQ_BIT: u16 = 1 << 1;
+const JGT_BIT: u16 = 1;
+
+/// Compute the output of the Hack ALU for the comp bits, `zx nx zy ny f no`, of a
+/// C-instruction; `x` is D and `y` is A or M.
+fn alu(word: u16, x: i16, y: i16) -> i16 {
+	let bit = |n: u16| word & (1 << n) != 0;
+	let x = if bit(11) { 0 } else { x };
+	let x = if bit(10) { !x } else { x };
+	let y = if bit(9) { 0 } else { y };
+	let y = if bit(8) { !y } else { y };
+	let out = if bit(7) { x.wrapping_add(y) } else { x & y };
+	if bit(6) { !out } else { out }
+}
+
+fn is_jump_taken(word: u16, out: i16) -> bool {
+	(word & JLT_BIT != 0 && out < 0) || (word & JEQ_BIT != 0 && out == 0) || (word & JGT_BIT != 0 && out > 0)
+}
+
+/// A model of the Hack computer which executes the binary words of a program assembled by
+/// the Hack assembler; so code which the assembler rejects, or which Hack cannot encode,
+/// fails to run rather than passing as it would on a looser model.
+pub struct Machine {
+	pub ram: Vec<i16>,
+	pub a: i16,
+	pub d: i16,
+	pub pc: usize,
+	rom: Vec<u16>,
+	symbols: HashMap<String, u16>,
+}
+
+impl Machine {
+	pub fn new(asm: &str) -> Result<Self, MachineError> {
+		let program = assemble_program(&mut asm.as_bytes(), &AssembleOptions::default())
+			.map_err(|_| MachineError::Assemble{error_count: 1})?;
+		if program.error_count > 0 {
+			return Err(MachineError::Assemble{error_count: program.error_count});
+		}
+		if let Some((address, word)) = program.words.iter().enumerate().find(|(_, word)| decode_ins(**word).is_none()) {
+			return Err(MachineError::InvalidIns{address, word: *word});
+		}
+		let symbols = program.symbols.into_iter().map(|sym| (sym.name, sym.address)).collect();
+		Ok(Machine{ram: vec![0; RAM_SIZE], a: 0, d: 0, pc: 0, rom: program.words, symbols})
+	}
+
+	/// The address of a label or variable.
+	pub fn symbol(&self, sym: &str) -> Option<u16> {
+		self.symbols.get(sym).copied()
+	}
+
+	pub fn step(&mut self) -> Result<(), MachineError> {
+		let pc = self.pc;
+		let word = *self.rom.get(pc).ok_or(MachineError::PcOutOfRange{pc})?;
+		if word & C_INS_BIT == 0 {
+			self.a = word as i16;
+			self.pc += 1;
+			return Ok(());
+		}
+		let address = self.a as u16 as usize;
+		let m = self.ram.get(address).copied().unwrap_or(0);
+		let out = alu(word, self.d, if word & A_BIT != 0 { m } else { self.a });
+		if word & DEST_M_BIT != 0 {
+			*self.ram.get_mut(address).ok_or(MachineError::RamOutOfRange{pc, address})? = out;
+		}
+		if word & DEST_D_BIT != 0 {
+			self.d = out;
+		}
+		let target = self.a;
+		if word & DEST_A_BIT != 0 {
+			self.a = out;
+		}
+		self.pc = match is_jump_taken(word, out) {
+			true => target as u16 as usize,
+			false => pc + 1,
+		};
+		Ok(())
+	}
+
+	/// Run until the program counter reaches `label`.
+	pub fn run_until(&mut self, label: &str, max_steps: usize) -> Result<(), MachineError> {
+		let end = self.symbol(label).ok_or_else(|| MachineError::UnknownLabel(label.to_string()))? as usize;
+		for _ in 0..max_steps {
+			if self.pc == end {
+				return Ok(());
+			}
+			self.step()?;
+		}
+		Err(MachineError::StepLimit)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_machine(){
+		// Sum 1..=10 into a variable.
+		let asm = "\
+			@10\n\
+			D=A\n\
+			@n\n\
+			M=D\n\
+			(LOOP)\n\
+			@n\n\
+			D=M\n\
+			@END\n\
+			D;JEQ // done\n\
+			@sum\n\
+			M=D+M\n\
+			@n\n\
+			M=M-1\n\
+			@LOOP\n\
+			0;JMP\n\
+			(END)\n\
+		";
+		let mut machine = Machine::new(asm).unwrap();
+		assert_eq!(machine.run_until("END", 1000), Ok(()));
+		assert_eq!((machine.symbol("n"), machine.symbol("sum")), (Some(16), Some(17)));
+		assert_eq!(machine.ram[17], 55);
+
+		// Jumps should use the value of A before the computation.
+		let mut machine = Machine::new("@3\nA=0;JMP\n(END)\n@0\n").unwrap();
+		assert_eq!(machine.run_until("END", 10), Err(MachineError::PcOutOfRange{pc: 3}));
+
+		// Code which Hack cannot encode should be rejected, as the assembler rejects it.
+		assert_eq!(Machine::new("@1\nD=A+\n").err(), Some(MachineError::Assemble{error_count: 1}));
+		assert_eq!(Machine::new("D=D;JMQ\n").err(), Some(MachineError::Assemble{error_count: 1}));
+		for comp in ["D+D", "A+M", "1-D", "M-A"] {
+			assert_eq!(Machine::new(&format!("D={}\n", comp)).err(), Some(MachineError::Assemble{error_count: 1}), "{}", comp);
+		}
+
+		// Every computation of the ALU should match its mnemonic.
+		let mut machine = Machine::new("@5\nD=A\n@3\nM=D-A\nD=!D\nA=1\nAM=D|A\nD=D&A\n").unwrap();
+		for _ in 0..4 {
+			machine.step().unwrap();
+		}
+		assert_eq!(machine.ram[3], 2);
+		for _ in 0..4 {
+			machine.step().unwrap();
+		}
+		assert_eq!((machine.a, machine.d, machine.ram[1]), (-5, -6, -5));
+		assert_eq!(Machine::new("@0\n0;JMP\n").unwrap().run_until("END", 10), Err(MachineError::UnknownLabel("END".to_string())));
+		assert_eq!(Machine::new("(L)\n@L\n0;JMP\n(END)\n").unwrap().run_until("END", 100), Err(MachineError::StepLimit));
+	}
+}
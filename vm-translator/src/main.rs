@@ -1,58 +1,83 @@
-use std::io::{BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 use std::fs::File;
-use crate::coder::*;
-use crate::tokenizer::*;
-use crate::parser::*;
-use crate::errors::*;
+use n2t_vm_translator::coder::*;
+use n2t_vm_translator::errors::*;
+use n2t_vm_translator::callgraph::*;
+use n2t_vm_translator::{audit, translate};
+use crate::cli::Emit;
 
-mod errors;
-mod tokenizer;
-mod parser;
-mod coder;
 mod cli;
 
-fn translate_file<W: Write>(file: PathBuf, coder: &mut Coder, ctx: &mut TranslationContext, out_file: &mut W) -> Result<(), TranslationError> {
-	let vm_file = BufReader::new(File::open(file)?);
-	let tokenizer = Tokenizer::new(vm_file);
-	let mut parser = Parser::new(tokenizer);
-	while let Some(ins) = parser.next() {
-		ctx.line.clear();
-		ctx.line.insert_str(0, parser.get_line());
-		ctx.line_num = parser.get_line_num();
-		let ins = ins?;
-		if let VmIns::Function{ref name, ..} = ins {
-			ctx.ins_ctx.vm_function_name = name.clone();
+fn write_counter_map(coder: &Coder, path: &Path) {
+	let result = File::create(path).and_then(|file| {
+		let mut out = BufWriter::new(file);
+		for (address, function) in coder.get_counters() {
+			writeln!(out, "{} {}", address, function)?;
 		}
-		coder.write_vm_ins(out_file, ins, &ctx.ins_ctx)?;
+		out.flush()
+	});
+	if let Err(e) = result {
+		println!("error: failed to write counter map '{}': {}", path.display(), e);
 	}
-	Ok(())
 }
 
-fn translate<W: Write>(in_files: Vec<PathBuf>, out_file: &mut W, ctx: &mut TranslationContext) -> Result<(), TranslationError> {
-	let mut coder = Coder::new();
-	coder.write_core_impl(out_file)?;
-	for path in in_files {
-		ctx.filepath = path.clone();
-		ctx.ins_ctx.vm_file_name = path.file_stem().unwrap().to_string_lossy().to_string().into();
-		translate_file(path, &mut coder, ctx, out_file)?;
+fn write_call_graph(call_graph: &CallGraph, path: &Path) {
+	let result = File::create(path).and_then(|file| {
+		let mut out = BufWriter::new(file);
+		call_graph.write_dot(&mut out)?;
+		out.flush()
+	});
+	if let Err(e) = result {
+		println!("error: failed to write call graph '{}': {}", path.display(), e);
 	}
-	Ok(())
 }
 
 fn main() {
 	let args = cli::parse_args();
-	let out_file = match File::create(args.output) {
+	let out_path = Path::new(&args.output).to_path_buf();
+	let out_file = match File::create(&out_path) {
 		Ok(file) => file,
 		Err(e) => {
 			println!("error: failed to create output .asm file: {}", e);
 			std::process::exit(0);
 		}
 	};
-	let mut buf_out_file = BufWriter::new(out_file);
+	let buf_out_file = BufWriter::new(out_file);
 	let mut ctx = TranslationContext::new();
-	match translate(args.input, &mut buf_out_file, &mut ctx) {
+	let mut call_graph = CallGraph::new();
+	let instrument = args.coder_options.instrument_base.is_some();
+	let coder_options = args.coder_options.clone();
+	let mut coder = Coder::new(args.coder_options);
+	let result = translate(args.input, buf_out_file, &mut ctx, &mut coder, &mut call_graph, args.parser_options);
+	for w in &ctx.warnings {
+		write_translation_warning(w);
+	}
+	match result {
 		Ok(()) => (),
-		Err(e) => write_translation_error(e, &mut ctx),
+		Err(e) => {
+			write_translation_error(e, &ctx);
+			return;
+		},
+	}
+	if args.audit {
+		let (known, failures): (Vec<_>, Vec<_>) = audit::audit(&coder_options).into_iter().partition(|f| f.known);
+		for failure in &known {
+			println!("audit known failure: '{}' overflows; {}", failure.case, failure.msg);
+		}
+		for failure in &failures {
+			println!("audit error: '{}'; {}", failure.case, failure.msg);
+		}
+		if !failures.is_empty() {
+			println!("Audit failed with {} errors", failures.len());
+			std::process::exit(-1);
+		}
+		println!("audit: all instruction templates match the vm specification");
+	}
+	if instrument {
+		write_counter_map(&coder, &out_path.with_extension("counters"));
+	}
+	if args.emit.contains(&Emit::Callgraph) {
+		write_call_graph(&call_graph, &out_path.with_extension("dot"));
 	}
 }
@@ -1,14 +1,19 @@
 use std::io::BufRead;
+use std::fmt;
+use core::ops::Range;
 use compact_str::CompactString;
 use crate::tokenizer::*;
 use crate::errors::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum VmIns {
 	Function{name: CompactString, locals_count: u16},
 	Call{function: CompactString, args_count: u16},
+	CallIndirect{args_count: u16},
 	Push{segment: VmSeg, index: u16},
 	Pop{segment: VmSeg, index: u16},
+	Inc{segment: VmSeg, index: u16},
+	Dec{segment: VmSeg, index: u16},
 	Label{label: CompactString},
 	IfGoto{label: CompactString},
 	Goto{label: CompactString},
@@ -24,13 +29,68 @@ pub enum VmIns {
 	Gt,
 }
 
+impl fmt::Display for VmIns {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			VmIns::Function{name, locals_count} => write!(f, "function {} {}", name, locals_count),
+			VmIns::Call{function, args_count} => write!(f, "call {} {}", function, args_count),
+			VmIns::CallIndirect{args_count} => write!(f, "call-indirect {}", args_count),
+			VmIns::Push{segment, index} => write!(f, "push {} {}", segment, index),
+			VmIns::Pop{segment, index} => write!(f, "pop {} {}", segment, index),
+			VmIns::Inc{segment, index} => write!(f, "inc {} {}", segment, index),
+			VmIns::Dec{segment, index} => write!(f, "dec {} {}", segment, index),
+			VmIns::Label{label} => write!(f, "label {}", label),
+			VmIns::IfGoto{label} => write!(f, "if-goto {}", label),
+			VmIns::Goto{label} => write!(f, "goto {}", label),
+			VmIns::Return => write!(f, "return"),
+			VmIns::Add => write!(f, "add"),
+			VmIns::Sub => write!(f, "sub"),
+			VmIns::Neg => write!(f, "neg"),
+			VmIns::And => write!(f, "and"),
+			VmIns::Or => write!(f, "or"),
+			VmIns::Not => write!(f, "not"),
+			VmIns::Eq => write!(f, "eq"),
+			VmIns::Lt => write!(f, "lt"),
+			VmIns::Gt => write!(f, "gt"),
+		}
+	}
+}
+
+/// Whether `identifier` is a symbol as defined by the official VM specification: letters,
+/// digits, '_', '.' and ':', not starting with a digit.
+pub fn is_spec_identifier(identifier: &str) -> bool {
+	!identifier.starts_with(|c: char| c.is_ascii_digit())
+		&& identifier.chars().all(|c| c.is_ascii_alphanumeric() || "_.:".contains(c))
+}
+
+/// Prefix of the labels the coder generates; labels declared by vm code may not start with it.
+pub const RESERVED_LABEL_PREFIX: &str = "__";
+
+/// Options which change the language accepted by the [`Parser`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParserOptions {
+	/// Reject identifiers which the official VM specification does not allow.
+	pub strict: bool,
+	/// Accept commands which extend the official VM specification.
+	pub extensions: bool,
+	/// Accept `pop constant i`, which discards the top of the stack; otherwise almost
+	/// certainly a mistake.
+	pub allow_pop_constant: bool,
+}
+
 pub struct Parser<R: BufRead> {
 	tokenizer: Tokenizer<R>,
+	options: ParserOptions,
+	ident_span: Range<usize>,
 }
 
 impl<R: BufRead> Parser<R> {
 	pub fn new(tokenizer: Tokenizer<R>) -> Self {
-		Parser{tokenizer}
+		Parser{tokenizer, options: ParserOptions::default(), ident_span: 0..0}
+	}
+
+	pub fn set_options(&mut self, options: ParserOptions) {
+		self.options = options;
 	}
 
 	pub fn get_line(&self) -> &str {
@@ -41,15 +101,45 @@ impl<R: BufRead> Parser<R> {
 		self.tokenizer.get_line_num()
 	}
 
+	/// Span of the last token of the last parsed instruction.
+	pub fn get_span(&self) -> Range<usize> {
+		self.tokenizer.get_span()
+	}
+
+	/// Span of the identifier of the last parsed instruction.
+	pub fn get_ident_span(&self) -> Range<usize> {
+		self.ident_span.clone()
+	}
+
+	/// Parse an identifier; the names of extension commands are identifiers unless extensions
+	/// are enabled, as they are not reserved by the official VM specification.
 	fn parse_identifier(&mut self) -> Result<CompactString, ParseError> {
 		return match self.tokenizer.next() {
-			Some(Ok(VmToken::Identifier(identifier))) => Ok(identifier),
+			Some(Ok(VmToken::Command(cmd))) if cmd.is_extension() && !self.options.extensions => {
+				self.ident_span = self.tokenizer.get_span();
+				Ok(cmd.to_string().into())
+			},
+			Some(Ok(VmToken::Identifier(identifier))) => {
+				self.ident_span = self.tokenizer.get_span();
+				if self.options.strict && !is_spec_identifier(&identifier) {
+					return Err(ParseError::InvalidIdentifier{identifier, span: self.ident_span.clone()});
+				}
+				Ok(identifier)
+			},
 			Some(Err(e)) => Err(ParseError::from(e)),
 			Some(Ok(token)) => Err(ParseError::ExpectedIdentifier{received: Some(token)}),
 			None => Err(ParseError::ExpectedIdentifier{received: None}),
 		}
 	}
 
+	fn parse_label(&mut self) -> Result<CompactString, ParseError> {
+		let label = self.parse_identifier()?;
+		if label.starts_with(RESERVED_LABEL_PREFIX) {
+			return Err(ParseError::ReservedLabel{label, span: self.ident_span.clone()});
+		}
+		Ok(label)
+	}
+
 	fn parse_int_const(&mut self) -> Result<u16, ParseError> {
 		return match self.tokenizer.next() {
 			Some(Ok(VmToken::IntConst(int))) => Ok(int),
@@ -59,6 +149,17 @@ impl<R: BufRead> Parser<R> {
 		}
 	}
 
+	fn parse_index(&mut self, segment: VmSeg) -> Result<u16, ParseError> {
+		let index = self.parse_int_const()?;
+		if let Some(max_index) = segment.max_index() {
+			if index > max_index {
+				let bounds = 0..max_index as usize;
+				return Err(ParseError::IndexOutOfBounds{segment, index, bounds, span: self.tokenizer.get_span()});
+			}
+		}
+		Ok(index)
+	}
+
 	fn parse_segment(&mut self) -> Result<VmSeg, ParseError> {
 		return match self.tokenizer.next() {
 			Some(Ok(VmToken::Segment(segment))) => Ok(segment),
@@ -69,15 +170,39 @@ impl<R: BufRead> Parser<R> {
 	}
 
 	fn parse_command(&mut self, cmd: VmCmd) -> Result<VmIns, ParseError> {
+		if cmd.is_extension() && !self.options.extensions {
+			return Err(ParseError::ExtensionDisabled{command: cmd, span: self.tokenizer.get_span()});
+		}
 		let ins = match cmd {
 			VmCmd::Function => VmIns::Function{name: self.parse_identifier()?, locals_count: self.parse_int_const()?},
 			VmCmd::Return => VmIns::Return,
-			VmCmd::Label => VmIns::Label{label: self.parse_identifier()?},
-			VmCmd::IfGoto => VmIns::IfGoto{label: self.parse_identifier()?},
-			VmCmd::Goto => VmIns::Goto{label: self.parse_identifier()?},
+			VmCmd::Label => VmIns::Label{label: self.parse_label()?},
+			VmCmd::IfGoto => VmIns::IfGoto{label: self.parse_label()?},
+			VmCmd::Goto => VmIns::Goto{label: self.parse_label()?},
 			VmCmd::Call => VmIns::Call{function: self.parse_identifier()?, args_count: self.parse_int_const()?},
-			VmCmd::Push => VmIns::Push{segment: self.parse_segment()?, index: self.parse_int_const()?},
-			VmCmd::Pop => VmIns::Pop{segment: self.parse_segment()?, index: self.parse_int_const()?},
+			VmCmd::CallIndirect => VmIns::CallIndirect{args_count: self.parse_int_const()?},
+			VmCmd::Push => {
+				let segment = self.parse_segment()?;
+				VmIns::Push{segment, index: self.parse_index(segment)?}
+			},
+			VmCmd::Pop => {
+				let segment = self.parse_segment()?;
+				if segment == VmSeg::Constant && !self.options.allow_pop_constant {
+					return Err(ParseError::PopConstant{span: self.tokenizer.get_span()});
+				}
+				VmIns::Pop{segment, index: self.parse_index(segment)?}
+			},
+			VmCmd::Inc | VmCmd::Dec => {
+				let segment = self.parse_segment()?;
+				if segment == VmSeg::Constant {
+					return Err(ParseError::ConstantSegment{command: cmd, span: self.tokenizer.get_span()});
+				}
+				let index = self.parse_index(segment)?;
+				match cmd {
+					VmCmd::Inc => VmIns::Inc{segment, index},
+					_ => VmIns::Dec{segment, index},
+				}
+			},
 			VmCmd::Add => VmIns::Add,
 			VmCmd::Sub => VmIns::Sub,
 			VmCmd::Neg => VmIns::Neg,
@@ -280,4 +405,106 @@ mod tests {
 		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Goto{label: CompactString::from("MAIN_LOOP_START")});
 		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Label{label: CompactString::from("END_PROGRAM")});
 	}
+
+	#[test]
+	fn test_index_out_of_bounds(){
+		let vm_code = "\
+			push temp 7
+			pop temp 8
+			push pointer 2
+			push constant 32768
+			push static 240
+			push local 32768
+		".to_string();
+
+		let reader = BufReader::new(Cursor::new(vm_code.into_bytes()));
+		let tokenizer = Tokenizer::new(reader);
+		let mut parser = Parser::new(tokenizer);
+
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Push{segment: VmSeg::Temp, index: 7});
+		for (segment, index, span) in [(VmSeg::Temp, 8, 12..13), (VmSeg::Pointer, 2, 16..17),
+			(VmSeg::Constant, 32768, 17..22), (VmSeg::Static, 240, 15..18)] {
+			match parser.next().unwrap() {
+				Err(ParseError::IndexOutOfBounds{segment: s, index: i, span: sp, ..}) => {
+					assert_eq!((s, i, sp), (segment, index, span));
+				},
+				r => panic!("expected index out of bounds, got {:?}", r),
+			}
+		}
+
+		// Pointer based segments are only bounded by the size of RAM.
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Push{segment: VmSeg::Local, index: 32768});
+	}
+
+	#[test]
+	fn test_strict_identifiers(){
+		let vm_code = "label loop$1\nlabel loop-1\nlabel a.b:c_D9\n";
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Label{label: CompactString::from("loop$1")});
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Label{label: CompactString::from("loop-1")});
+
+		// Only the characters of the official specification should be accepted in strict mode.
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		parser.set_options(ParserOptions{strict: true, ..ParserOptions::default()});
+		assert!(matches!(parser.next(), Some(Err(ParseError::InvalidIdentifier{span, ..})) if span == (6..12)));
+		assert!(matches!(parser.next(), Some(Err(ParseError::InvalidIdentifier{span, ..})) if span == (6..12)));
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Label{label: CompactString::from("a.b:c_D9")});
+		assert_eq!(parser.get_ident_span(), 6..14);
+	}
+
+	#[test]
+	fn test_reserved_labels(){
+		let vm_code = "label __eq.1\ngoto __ret.1\nlabel eq.1\n";
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+
+		// Labels should not take the form of the labels generated by the coder.
+		assert!(matches!(parser.next(), Some(Err(ParseError::ReservedLabel{span, ..})) if span == (6..12)));
+		assert!(matches!(parser.next(), Some(Err(ParseError::ReservedLabel{span, ..})) if span == (5..12)));
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Label{label: CompactString::from("eq.1")});
+	}
+
+	#[test]
+	fn test_pop_constant(){
+		let code = "pop constant 3\n";
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(code))));
+		assert!(matches!(parser.next(), Some(Err(ParseError::PopConstant{span})) if span == (4..12)));
+
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(code))));
+		parser.set_options(ParserOptions{allow_pop_constant: true, ..ParserOptions::default()});
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Pop{segment: VmSeg::Constant, index: 3});
+	}
+
+	#[test]
+	fn test_extensions(){
+		let vm_code = "call-indirect 2\n";
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		assert!(matches!(parser.next(), Some(Err(ParseError::ExtensionDisabled{command: VmCmd::CallIndirect, span})) if span == (0..13)));
+
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		parser.set_options(ParserOptions{extensions: true, ..ParserOptions::default()});
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::CallIndirect{args_count: 2});
+
+		let vm_code = "inc local 2\ndec static 0\ninc constant 1\n";
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		assert!(matches!(parser.next(), Some(Err(ParseError::ExtensionDisabled{command: VmCmd::Inc, ..}))));
+
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		parser.set_options(ParserOptions{extensions: true, ..ParserOptions::default()});
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Inc{segment: VmSeg::Local, index: 2});
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Dec{segment: VmSeg::Static, index: 0});
+		assert!(matches!(parser.next(), Some(Err(ParseError::ConstantSegment{command: VmCmd::Inc, span})) if span == (4..12)));
+
+		// The names of extensions should only be reserved when extensions are enabled.
+		let vm_code = "function inc 0
+label dec
+call inc 0
+";
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Function{name: CompactString::from("inc"), locals_count: 0});
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Label{label: CompactString::from("dec")});
+		assert_eq!(parser.next().unwrap().unwrap(), VmIns::Call{function: CompactString::from("inc"), args_count: 0});
+		let mut parser = Parser::new(Tokenizer::new(BufReader::new(Cursor::new(vm_code))));
+		parser.set_options(ParserOptions{extensions: true, ..ParserOptions::default()});
+		assert!(matches!(parser.next(), Some(Err(ParseError::ExpectedIdentifier{received: Some(VmToken::Command(VmCmd::Inc))}))));
+	}
 }
@@ -1,5 +1,6 @@
 use std::io::BufRead;
 use std::str::FromStr;
+use core::ops::Range;
 use compact_str::CompactString;
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -14,8 +15,11 @@ pub enum VmCmd {
 	IfGoto,
 	Goto,
 	Call,
+	CallIndirect,
 	Push,
 	Pop,
+	Inc,
+	Dec,
 	Add,
 	Sub,
 	Neg,
@@ -36,12 +40,15 @@ impl fmt::Display for VmCmd {
 			VmCmd::IfGoto   => "if-goto",
 			VmCmd::Goto     => "goto",
 			VmCmd::Call     => "call",
+			VmCmd::CallIndirect => "call-indirect",
 			VmCmd::Push     => "push",
-			VmCmd::Pop     => "pop",
+			VmCmd::Pop      => "pop",
+			VmCmd::Inc      => "inc",
+			VmCmd::Dec      => "dec",
 			VmCmd::Add      => "add",
 			VmCmd::Sub      => "sub",
 			VmCmd::Neg      => "neg",
-			VmCmd::And      => "adn",
+			VmCmd::And      => "and",
 			VmCmd::Or       => "or",
 			VmCmd::Not      => "not",
 			VmCmd::Eq       => "eq",
@@ -52,6 +59,14 @@ impl fmt::Display for VmCmd {
 	}
 }
 
+impl VmCmd {
+	/// Whether the command is an extension to the official VM specification, only
+	/// available with `--ext`.
+	pub fn is_extension(&self) -> bool {
+		matches!(self, VmCmd::CallIndirect | VmCmd::Inc | VmCmd::Dec)
+	}
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum VmSeg {
 	Argument,
@@ -80,6 +95,20 @@ impl fmt::Display for VmSeg {
 	}
 }
 
+impl VmSeg {
+	/// The largest index a segment can be addressed with, or `None` if the segment is
+	/// only bounded by the size of RAM.
+	pub fn max_index(&self) -> Option<u16> {
+		match self {
+			VmSeg::Constant => Some(32767),
+			VmSeg::Static   => Some(239),
+			VmSeg::Pointer  => Some(1),
+			VmSeg::Temp     => Some(7),
+			_               => None,
+		}
+	}
+}
+
 #[derive(Debug, PartialEq)]
 pub enum VmToken {
 	Command(VmCmd),
@@ -97,9 +126,23 @@ impl fmt::Display for VmToken {
 impl FromStr for VmToken {
 	type Err = TokenError;
 	fn from_str(word: &str) -> Result<Self, Self::Err> {
+		VmToken::from_word(word, 0..word.len())
+	}
+}
+
+impl VmToken {
+	/// Classify a single whitespace delimited word; `span` is the word's byte range within
+	/// its source line and is only used to locate errors.
+	pub fn from_word(word: &str, span: Range<usize>) -> Result<Self, TokenError> {
 		if let Ok(x) = word.parse::<u16>(){
 			return Ok(VmToken::IntConst(x));
 		}
+		if word.starts_with(|c: char| c.is_ascii_digit()) {
+			if word.bytes().all(|b| b.is_ascii_digit()) {
+				return Err(TokenError::IntOverflow{word: CompactString::from(word), span});
+			}
+			return Err(TokenError::InvalidToken{word: CompactString::from(word), span});
+		}
 		let cmd = match word {
 			"function" => Some(VmToken::Command(VmCmd::Function)),
 			"return"   => Some(VmToken::Command(VmCmd::Return)),
@@ -107,8 +150,11 @@ impl FromStr for VmToken {
 			"if-goto"  => Some(VmToken::Command(VmCmd::IfGoto)),
 			"goto"     => Some(VmToken::Command(VmCmd::Goto)),
 			"call"     => Some(VmToken::Command(VmCmd::Call)),
+			"call-indirect" => Some(VmToken::Command(VmCmd::CallIndirect)),
 			"push"     => Some(VmToken::Command(VmCmd::Push)),
 			"pop"      => Some(VmToken::Command(VmCmd::Pop)),
+			"inc"      => Some(VmToken::Command(VmCmd::Inc)),
+			"dec"      => Some(VmToken::Command(VmCmd::Dec)),
 			"add"      => Some(VmToken::Command(VmCmd::Add)),
 			"sub"      => Some(VmToken::Command(VmCmd::Sub)),
 			"neg"      => Some(VmToken::Command(VmCmd::Neg)),
@@ -143,20 +189,26 @@ impl FromStr for VmToken {
 		if TOKEN.is_match(word) {
 			return Ok(VmToken::Identifier(CompactString::from(word)));
 		}
-		Err(TokenError::InvalidToken{word: CompactString::from(word)})
+		Err(TokenError::InvalidToken{word: CompactString::from(word), span})
 	}
 }
 
 pub struct Tokenizer<R: BufRead> {
 	reader: R,
-	tokens: Vec<VmToken>,
+	tokens: Vec<(VmToken, Range<usize>)>,
+	span: Range<usize>,
 	line: String,
 	line_num: usize,
 }
 
 impl<R: BufRead> Tokenizer<R> {
 	pub fn new(reader: R) -> Self {
-		Tokenizer{reader, tokens: Vec::new(), line: String::new(), line_num: 0}
+		Tokenizer{reader, tokens: Vec::new(), span: 0..0, line: String::new(), line_num: 0}
+	}
+
+	/// Byte range, within the current line, of the last token returned by the tokenizer.
+	pub fn get_span(&self) -> Range<usize> {
+		self.span.clone()
 	}
 
 	pub fn get_line(&self) -> &str {
@@ -189,9 +241,12 @@ impl<R: BufRead> Iterator for Tokenizer<R> {
 					static ref WORDS: Regex = Regex::new(r"[\S]+").expect("error compiling WORDS regex");
 				}
 				for word in WORDS.find_iter(s) {
-					match word.as_str().parse::<VmToken>() {
-						Ok(t) => self.tokens.push(t),
-						Err(e) => return Some(Err(e)),
+					match VmToken::from_word(word.as_str(), word.range()) {
+						Ok(t) => self.tokens.push((t, word.range())),
+						Err(e) => {
+							self.tokens.clear(); // discard the rest of the erroneous line
+							return Some(Err(e));
+						},
 					}
 				}
 				if !self.tokens.is_empty() {
@@ -200,10 +255,9 @@ impl<R: BufRead> Iterator for Tokenizer<R> {
 			}
 			self.tokens.reverse();
 		}
-		match self.tokens.pop() {
-			Some(t) => Some(Ok(t)),
-			None => None,
-		}
+		let (token, span) = self.tokens.pop()?;
+		self.span = span;
+		Some(Ok(token))
 	}
 }
 
@@ -257,7 +311,7 @@ mod tests {
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::IntConst(1));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Command(VmCmd::Sub));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Command(VmCmd::Return));
-		assert_eq!(tokenizer.next().is_none(), true);
+		assert!(tokenizer.next().is_none());
 	}
 
 	#[test]
@@ -318,7 +372,7 @@ mod tests {
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::IntConst(1));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Command(VmCmd::Sub));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Command(VmCmd::Return));
-		assert_eq!(tokenizer.next().is_none(), true);
+		assert!(tokenizer.next().is_none());
 	}
 
 	#[test]
@@ -386,7 +440,7 @@ mod tests {
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Identifier(CompactString::from("WHILE")));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Command(VmCmd::Goto));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Identifier(CompactString::from("WHILE")));
-		assert_eq!(tokenizer.next().is_none(), true);
+		assert!(tokenizer.next().is_none());
 	}
 
 	#[test]
@@ -519,6 +573,35 @@ mod tests {
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Identifier(CompactString::from("MAIN_LOOP_START")));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Command(VmCmd::Label));
 		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Identifier(CompactString::from("END_PROGRAM")));
-		assert_eq!(tokenizer.next().is_none(), true);
+		assert!(tokenizer.next().is_none());
+	}
+
+	#[test]
+	fn test_malformed_ints(){
+		let vm_code = "\
+			push constant 65535
+			push constant 99999
+			push local 12ab
+		".to_string();
+
+		let reader = BufReader::new(Cursor::new(vm_code.into_bytes()));
+		let mut tokenizer = Tokenizer::new(reader);
+
+		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Command(VmCmd::Push));
+		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::Segment(VmSeg::Constant));
+		assert_eq!(tokenizer.next().unwrap().unwrap(), VmToken::IntConst(65535));
+		assert_eq!(tokenizer.get_span(), 14..19);
+
+		// Numeric words which overflow a u16 should not be mistaken for identifiers.
+		match tokenizer.next().unwrap() {
+			Err(TokenError::IntOverflow{word, span}) => assert_eq!((word.as_str(), span), ("99999", 17..22)),
+			r => panic!("expected int overflow, got {:?}", r),
+		}
+
+		// Words starting with a digit are neither integers nor identifiers.
+		match tokenizer.next().unwrap() {
+			Err(TokenError::InvalidToken{word, span}) => assert_eq!((word.as_str(), span), ("12ab", 14..18)),
+			r => panic!("expected invalid token, got {:?}", r),
+		}
 	}
 }